@@ -0,0 +1,244 @@
+use crate::download::download_flow_judge_llamafile;
+use crate::models::{AppError, Config, RunParams};
+use crate::report::render_table;
+use crate::{
+    available_threads, execute_llamafile_with_timeout, llamafile_path, load_rubric,
+    populate_template, read_items,
+};
+use console::style;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use log::info;
+use minijinja::context;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Rough number of characters per token, for llamafiles that print no timings.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Throughput of judging the benchmark items with one configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub batch_size: usize,
+    /// Threads of each llamafile process.
+    pub threads: usize,
+    pub items: usize,
+    pub tokens: usize,
+    /// Whether the tokens were estimated from the output length.
+    pub estimated: bool,
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    #[allow(clippy::cast_precision_loss)]
+    pub fn tokens_per_second(&self) -> f64 {
+        self.tokens as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// The batch sizes and threads per process to measure.
+///
+/// Without explicit lists, batch sizes of 1, 2, 4 and 8 up to the available
+/// threads are tried, each with the thread budget split evenly across the
+/// workers and with half of that.
+pub fn configurations(
+    available: usize,
+    batch_sizes: &[usize],
+    threads: &[usize],
+) -> Vec<(usize, usize)> {
+    let batch_sizes: Vec<usize> = if batch_sizes.is_empty() {
+        [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&size| size == 1 || size <= available)
+            .collect()
+    } else {
+        batch_sizes.iter().map(|&size| size.max(1)).collect()
+    };
+
+    let mut configurations = Vec::new();
+    for batch_size in batch_sizes {
+        let candidates = if threads.is_empty() {
+            let share = (available / batch_size).max(1);
+            vec![share, (share / 2).max(1)]
+        } else {
+            threads.iter().map(|&count| count.max(1)).collect()
+        };
+        for count in candidates {
+            if !configurations.contains(&(batch_size, count)) {
+                configurations.push((batch_size, count));
+            }
+        }
+    }
+    configurations
+}
+
+/// The measurement with the highest throughput.
+pub fn recommend(measurements: &[Measurement]) -> Option<&Measurement> {
+    measurements
+        .iter()
+        .max_by(|a, b| a.tokens_per_second().total_cmp(&b.tokens_per_second()))
+}
+
+/// Judges `prompts` with `batch_size` concurrent llamafile processes of
+/// `threads` threads each.
+async fn measure(
+    prompts: &[String],
+    llamafile_path: &Path,
+    params: &RunParams,
+    batch_size: usize,
+    threads: usize,
+) -> Result<Measurement, AppError> {
+    let params = RunParams {
+        thread_count: Some(threads),
+        ..params.clone()
+    };
+    let start = Instant::now();
+    let runs: Vec<_> = stream::iter(prompts)
+        .map(|prompt| execute_llamafile_with_timeout(prompt, llamafile_path, &params))
+        .buffer_unordered(batch_size)
+        .try_collect()
+        .await?;
+    let elapsed = start.elapsed();
+
+    let estimated = runs.iter().any(|run| run.tokens.is_none());
+    let tokens = runs
+        .iter()
+        .map(|run| {
+            run.tokens
+                .unwrap_or_else(|| run.output.len().div_ceil(CHARS_PER_TOKEN))
+        })
+        .sum();
+    Ok(Measurement {
+        batch_size,
+        threads,
+        items: prompts.len(),
+        tokens,
+        estimated,
+        elapsed,
+    })
+}
+
+/// Runs `fwj bench`: judges the same items with every configuration,
+/// prints the throughput of each and recommends the fastest.
+pub async fn run(
+    config: &Config,
+    params: &RunParams,
+    rubric_template: &str,
+    data_path: &str,
+    item_count: usize,
+    batch_sizes: &[usize],
+    threads: &[usize],
+) -> Result<Vec<Measurement>, AppError> {
+    let rubric = load_rubric(rubric_template).await?;
+    let items = read_items(data_path)?;
+    if items.is_empty() || item_count == 0 {
+        return Err(AppError::ConfigError(format!(
+            "No items to benchmark with in {}",
+            data_path
+        )));
+    }
+    let prompts = items
+        .iter()
+        .cycle()
+        .take(item_count)
+        .map(|item| {
+            populate_template(
+                &rubric,
+                &context! {
+                    input => item.input().unwrap_or_default(),
+                    output => item.output().unwrap_or_default(),
+                },
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if params.model.is_none() {
+        info!("Downloading Flow Judge llamafile");
+        download_flow_judge_llamafile(config).await?;
+    }
+    let llamafile_path = llamafile_path(config, params);
+    let available = available_threads();
+    let configurations = configurations(available, batch_sizes, threads);
+
+    println!(
+        "{}",
+        style(format!(
+            "Benchmarking {} configurations with {} items each ({} threads available)",
+            configurations.len(),
+            prompts.len(),
+            available
+        ))
+        .yellow()
+        .bold()
+    );
+    // Loads the model into the page cache, so the first configuration is not penalized
+    println!("{}", style("Warming up...").dim());
+    execute_llamafile_with_timeout(&prompts[0], &llamafile_path, params).await?;
+
+    let mut measurements = Vec::with_capacity(configurations.len());
+    for (batch_size, threads) in configurations {
+        println!(
+            "  Batch size {} with {} threads per process...",
+            batch_size, threads
+        );
+        measurements.push(measure(&prompts, &llamafile_path, params, batch_size, threads).await?);
+    }
+
+    print_measurements(&measurements, available);
+    Ok(measurements)
+}
+
+fn print_measurements(measurements: &[Measurement], available: usize) {
+    let headers = [
+        "Batch size",
+        "Threads/process",
+        "Items",
+        "Tokens",
+        "Time",
+        "Tokens/s",
+    ]
+    .map(str::to_string);
+    let rows: Vec<Vec<String>> = measurements
+        .iter()
+        .map(|measurement| {
+            vec![
+                measurement.batch_size.to_string(),
+                measurement.threads.to_string(),
+                measurement.items.to_string(),
+                format!(
+                    "{}{}",
+                    if measurement.estimated { "~" } else { "" },
+                    measurement.tokens
+                ),
+                format!("{:.2} s", measurement.elapsed.as_secs_f64()),
+                format!("{:.1}", measurement.tokens_per_second()),
+            ]
+        })
+        .collect();
+    println!();
+    print!("{}", render_table(&headers, &rows, false));
+    if measurements.iter().any(|measurement| measurement.estimated) {
+        println!(
+            "{}",
+            style("~ Estimated from the output length; llamafile printed no timings.").dim()
+        );
+    }
+
+    if let Some(best) = recommend(measurements) {
+        let thread_flag = if best.threads == (available / best.batch_size).max(1) {
+            String::new()
+        } else {
+            format!(" --thread-count {}", best.threads)
+        };
+        println!(
+            "\n{} {}",
+            style("Recommended:").green().bold(),
+            style(format!(
+                "--batch-size {}{} ({:.1} tokens/s)",
+                best.batch_size,
+                thread_flag,
+                best.tokens_per_second()
+            ))
+            .green()
+        );
+    }
+}
@@ -0,0 +1,624 @@
+use crate::cache::{
+    hash_with_progress, lock_path, manifest_path, record_sha256, sha256_file, verify_model,
+    LfsPointer, Manifest, Status, LLAMAFILE_NAME,
+};
+use crate::config::{read_config_value, render_config_value};
+use crate::download::LLAMAFILE_VERSION;
+use crate::lock::FileLock;
+use crate::models::{AppError, Config};
+use crate::store::sha256_hex;
+use console::style;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use indicatif::HumanBytes;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the manifest inside a bundle, written before every other entry.
+const MANIFEST_NAME: &str = "bundle.json";
+/// Version of the bundle layout, bumped when it changes incompatibly.
+const FORMAT_VERSION: u32 = 1;
+/// Where the model and its lock file are kept inside a bundle.
+const MODEL_DIR: &str = "model";
+
+/// Archive formats a bundle can be written as, chosen by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    TarGz,
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    // The name is lowercased, and `.tar.gz` is more than one extension
+    #[allow(clippy::case_sensitive_file_extension_comparisons)]
+    pub fn from_path(path: &Path) -> Result<Self, AppError> {
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_ascii_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar") {
+            Ok(ArchiveFormat::Tar)
+        } else if name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else {
+            Err(AppError::BundleError(format!(
+                "Unsupported archive '{}': use .tar.gz, .tgz, .tar or .zip",
+                path.display()
+            )))
+        }
+    }
+}
+
+/// A file packed into a bundle, with the hash it is verified against.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleFile {
+    /// Path inside the bundle, with `/` separators.
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// The `bundle.json` listing everything in a bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub format_version: u32,
+    /// When the bundle was made, in RFC 3339.
+    pub created_at: String,
+    /// Where the bundled model was downloaded from, if known.
+    pub model: Option<Manifest>,
+    /// The config file, if one was bundled.
+    pub config: Option<String>,
+    pub files: Vec<BundleFile>,
+}
+
+impl BundleManifest {
+    fn new(model: &Path, config: Option<String>, entries: &[Entry]) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            model: Manifest::read(&manifest_path(model)),
+            config,
+            files: entries.iter().map(|entry| entry.file.clone()).collect(),
+        }
+    }
+
+    fn find(&self, path: &str) -> Option<&BundleFile> {
+        self.files.iter().find(|file| file.path == path)
+    }
+}
+
+/// Where the contents of a packed file come from.
+enum Source {
+    /// Only the first `size` bytes of the file are bundled.
+    File(PathBuf),
+    /// Contents made during export, such as a rewritten config file.
+    Data(Vec<u8>),
+}
+
+/// A file to pack.
+struct Entry {
+    file: BundleFile,
+    source: Source,
+    mode: u32,
+}
+
+impl Entry {
+    fn reader(&self) -> Result<Box<dyn Read + '_>, AppError> {
+        Ok(match &self.source {
+            Source::File(path) => Box::new(File::open(path)?.take(self.file.size)),
+            Source::Data(data) => Box::new(data.as_slice()),
+        })
+    }
+}
+
+fn model_entry_name(name: &str) -> String {
+    format!("{}/{}", MODEL_DIR, name)
+}
+
+/// Whether `path` is bundled under a different path than its own, because it
+/// is absolute or leaves the current directory.
+fn is_relocated(path: &Path) -> bool {
+    !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// The path of `path` inside a bundle: relative paths are kept, so the
+/// config keeps pointing at its rubric templates; others go into `rubrics/`.
+fn entry_name(path: &Path) -> String {
+    if is_relocated(path) {
+        return format!(
+            "rubrics/{}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+    }
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// A path read from a bundle, rejected unless it stays inside the bundle.
+fn safe_path(name: &str) -> Result<PathBuf, AppError> {
+    let path = PathBuf::from(name);
+    if name.is_empty()
+        || !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(AppError::BundleError(format!(
+            "Refusing to unpack '{}' outside of the bundle",
+            name
+        )));
+    }
+    Ok(path)
+}
+
+/// The rubric templates to bundle: everything in the rubrics directory and
+/// the templates of the configured tasks.
+fn rubric_templates(config: &Config) -> Result<Vec<PathBuf>, AppError> {
+    let mut templates = Vec::new();
+    let rubrics_dir = Path::new(&config.rubrics_dir);
+    if rubrics_dir.is_dir() {
+        for entry in std::fs::read_dir(rubrics_dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                templates.push(path);
+            }
+        }
+        templates.sort();
+    }
+    for task in &config.tasks {
+        let path = PathBuf::from(&task.rubric_template);
+        if task.rubric_template == "fetch" || templates.contains(&path) {
+            continue;
+        }
+        if !path.is_file() {
+            return Err(AppError::FileReadError(format!(
+                "Rubric template '{}' not found",
+                path.display()
+            )));
+        }
+        templates.push(path);
+    }
+    Ok(templates)
+}
+
+fn file_entry(path: &Path, name: String) -> Result<Entry, AppError> {
+    let size = std::fs::metadata(path)
+        .map_err(|e| {
+            AppError::FileReadError(format!("Failed to read '{}': {}", path.display(), e))
+        })?
+        .len();
+    Ok(Entry {
+        file: BundleFile {
+            path: name,
+            size,
+            sha256: sha256_file(path, None, None)?,
+        },
+        source: Source::File(path.to_path_buf()),
+        mode: 0o644,
+    })
+}
+
+/// The entries of the cached model and its lock file, which must verify.
+fn model_entries(model: &Path) -> Result<Vec<Entry>, AppError> {
+    let size = std::fs::metadata(model)?.len();
+    let (sha256, status) = verify_model(model, size)?;
+    let (Status::Verified, Some(sha256), Some(pointer)) =
+        (status, sha256, LfsPointer::read(&lock_path(model)))
+    else {
+        return Err(AppError::BundleError(format!(
+            "The cached model {} does not verify against its lock file; see fwj cache verify",
+            model.display()
+        )));
+    };
+
+    Ok(vec![
+        Entry {
+            file: BundleFile {
+                path: model_entry_name(LLAMAFILE_NAME),
+                size: pointer.size,
+                sha256,
+            },
+            source: Source::File(model.to_path_buf()),
+            mode: 0o755,
+        },
+        file_entry(
+            &lock_path(model),
+            model_entry_name(&format!("{}.lock", LLAMAFILE_NAME)),
+        )?,
+    ])
+}
+
+/// The entry of the config file, if there is one. Rubric templates of its
+/// tasks that are bundled under `rubrics/` are pointed there, so the
+/// imported config finds them.
+fn config_entry(config_file: &str) -> Result<Option<Entry>, AppError> {
+    let path = Path::new(config_file);
+    if !path.is_file() {
+        info!("No config file at {}, bundling without one", config_file);
+        return Ok(None);
+    }
+    let name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+
+    let mut value = read_config_value(config_file)?;
+    let mut rewritten = false;
+    if let Some(Value::Array(tasks)) = value.get_mut("tasks") {
+        for task in tasks {
+            if let Some(Value::String(template)) = task.get_mut("rubric_template") {
+                if is_relocated(Path::new(template.as_str())) {
+                    *template = entry_name(Path::new(template.as_str()));
+                    rewritten = true;
+                }
+            }
+        }
+    }
+    if !rewritten {
+        return file_entry(path, name).map(Some);
+    }
+
+    let data = render_config_value(config_file, &value)?.into_bytes();
+    Ok(Some(Entry {
+        file: BundleFile {
+            path: name,
+            size: data.len() as u64,
+            sha256: sha256_hex(&data),
+        },
+        source: Source::Data(data),
+        mode: 0o644,
+    }))
+}
+
+/// Runs `fwj bundle export`: packs the verified Flow-Judge model with its
+/// lock file, the rubric templates and the config file into `output`.
+pub fn export(config: &Config, config_file: &str, output: &Path) -> Result<(), AppError> {
+    let format = ArchiveFormat::from_path(output)?;
+    let model = Path::new(&config.cache_dir).join(LLAMAFILE_NAME);
+    if !model.is_file() {
+        return Err(AppError::BundleError(format!(
+            "No model cached at {}; run fwj once to download it",
+            model.display()
+        )));
+    }
+
+    // The model must not change while it is verified and packed
+    let _lock = FileLock::shared(&model)?;
+    let mut entries = model_entries(&model)?;
+    for template in rubric_templates(config)? {
+        let name = entry_name(&template);
+        if entries.iter().all(|entry| entry.file.path != name) {
+            entries.push(file_entry(&template, name)?);
+        }
+    }
+    let config_name = config_entry(config_file)?.map(|entry| {
+        let name = entry.file.path.clone();
+        entries.push(entry);
+        name
+    });
+    let manifest = BundleManifest::new(&model, config_name, &entries);
+
+    println!(
+        "Packing {} files into {}",
+        entries.len(),
+        style(output.display()).yellow()
+    );
+    write_archive(format, output, &manifest, &entries)?;
+
+    let total: u64 = entries.iter().map(|entry| entry.file.size).sum();
+    println!(
+        "{}",
+        style(format!(
+            "Bundle written to {} ({} of files)",
+            output.display(),
+            HumanBytes(total)
+        ))
+        .green()
+    );
+    Ok(())
+}
+
+/// Writes the bundle to a temporary file next to `output` and moves it into
+/// place once complete.
+fn write_archive(
+    format: ArchiveFormat,
+    output: &Path,
+    manifest: &BundleManifest,
+    entries: &[Entry],
+) -> Result<(), AppError> {
+    let dir = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let temp_file = tempfile::NamedTempFile::new_in(dir).map_err(|e| {
+        AppError::FileWriteError(format!(
+            "Failed to create temporary file next to '{}': {}",
+            output.display(),
+            e
+        ))
+    })?;
+    let manifest_json = serde_json::to_vec_pretty(manifest)?;
+    match format {
+        ArchiveFormat::TarGz => {
+            let encoder = GzEncoder::new(temp_file.as_file(), Compression::default());
+            write_tar(encoder, &manifest_json, entries)?.finish()?;
+        }
+        ArchiveFormat::Tar => {
+            write_tar(temp_file.as_file(), &manifest_json, entries)?;
+        }
+        ArchiveFormat::Zip => write_zip(temp_file.as_file(), &manifest_json, entries)?,
+    }
+    temp_file.as_file().sync_all()?;
+    temp_file.persist(output).map_err(|e| {
+        AppError::FileWriteError(format!("Failed to write '{}': {}", output.display(), e))
+    })?;
+    Ok(())
+}
+
+fn tar_header(size: u64, mode: u32) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(mode);
+    header.set_mtime(u64::try_from(chrono::Utc::now().timestamp()).unwrap_or_default());
+    header
+}
+
+fn write_tar<W: Write>(writer: W, manifest: &[u8], entries: &[Entry]) -> Result<W, AppError> {
+    let mut builder = tar::Builder::new(writer);
+    let mut header = tar_header(manifest.len() as u64, 0o644);
+    builder.append_data(&mut header, MANIFEST_NAME, manifest)?;
+    for entry in entries {
+        let mut header = tar_header(entry.file.size, entry.mode);
+        builder.append_data(&mut header, &entry.file.path, entry.reader()?)?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+impl From<zip::result::ZipError> for AppError {
+    fn from(e: zip::result::ZipError) -> Self {
+        AppError::BundleError(e.to_string())
+    }
+}
+
+fn write_zip(file: &File, manifest: &[u8], entries: &[Entry]) -> Result<(), AppError> {
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default().unix_permissions(0o644);
+    zip.start_file(MANIFEST_NAME, options)?;
+    zip.write_all(manifest)?;
+    for entry in entries {
+        // Model weights barely compress, so they are stored as they are
+        let method = if entry.mode == 0o755 {
+            zip::CompressionMethod::Stored
+        } else {
+            zip::CompressionMethod::Deflated
+        };
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(method)
+            .unix_permissions(entry.mode)
+            .large_file(entry.file.size >= u64::from(u32::MAX));
+        zip.start_file(entry.file.path.as_str(), options)?;
+        std::io::copy(&mut entry.reader()?, &mut zip)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+fn unpack(format: ArchiveFormat, archive: &Path, dir: &Path) -> Result<(), AppError> {
+    let file = File::open(archive).map_err(|e| {
+        AppError::FileReadError(format!("Failed to open '{}': {}", archive.display(), e))
+    })?;
+    match format {
+        ArchiveFormat::TarGz => unpack_tar(GzDecoder::new(file), dir),
+        ArchiveFormat::Tar => unpack_tar(file, dir),
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipArchive::new(file)?;
+            for index in 0..zip.len() {
+                let mut entry = zip.by_index(index)?;
+                let path = dir.join(safe_path(entry.name())?);
+                if entry.is_dir() {
+                    std::fs::create_dir_all(&path)?;
+                    continue;
+                }
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::io::copy(&mut entry, &mut File::create(&path)?)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn unpack_tar<R: Read>(reader: R, dir: &Path) -> Result<(), AppError> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        safe_path(&name)?;
+        entry.unpack_in(dir)?;
+    }
+    Ok(())
+}
+
+/// Checks the unpacked files against the hashes in the manifest.
+fn verify_files(dir: &Path, manifest: &BundleManifest) -> Result<(), AppError> {
+    for file in &manifest.files {
+        let path = dir.join(safe_path(&file.path)?);
+        let size = std::fs::metadata(&path)
+            .map_err(|_| {
+                AppError::BundleError(format!("{} is missing from the bundle", file.path))
+            })?
+            .len();
+        if size != file.size {
+            return Err(AppError::BundleError(format!(
+                "{} has {} bytes, expected {}",
+                file.path, size, file.size
+            )));
+        }
+        let sha256 = hash_with_progress(&path, size)?;
+        if sha256 != file.sha256 {
+            return Err(AppError::BundleError(format!(
+                "{} has SHA-256 {}, expected {}",
+                file.path, sha256, file.sha256
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Runs `fwj bundle import`: unpacks `archive`, verifies every file against
+/// the bundle manifest and the model against its lock file, then installs
+/// the model into the cache directory and the rubric templates and config
+/// into `dest`.
+///
+/// Existing rubric templates and config files that differ from the bundled
+/// ones are only replaced with `force`.
+pub fn import(config: &Config, archive: &Path, dest: &Path, force: bool) -> Result<(), AppError> {
+    let format = ArchiveFormat::from_path(archive)?;
+    let cache_dir = Path::new(&config.cache_dir);
+    std::fs::create_dir_all(cache_dir)?;
+    // Unpacked next to the cache, so the model is moved rather than copied
+    let staging = tempfile::Builder::new()
+        .prefix(".bundle-")
+        .tempdir_in(cache_dir)?;
+
+    println!("Unpacking {}", style(archive.display()).yellow());
+    unpack(format, archive, staging.path())?;
+    let manifest: BundleManifest = std::fs::read_to_string(staging.path().join(MANIFEST_NAME))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .ok_or_else(|| {
+            AppError::BundleError(format!(
+                "{} is not a bundle made by fwj bundle export",
+                archive.display()
+            ))
+        })?;
+    if manifest.format_version != FORMAT_VERSION {
+        return Err(AppError::BundleError(format!(
+            "Unsupported bundle format version {}",
+            manifest.format_version
+        )));
+    }
+
+    println!("Verifying {} files", manifest.files.len());
+    verify_files(staging.path(), &manifest)?;
+    let model_name = model_entry_name(LLAMAFILE_NAME);
+    let lock_name = model_entry_name(&format!("{}.lock", LLAMAFILE_NAME));
+    let (Some(model), Some(pointer)) = (
+        manifest.find(&model_name),
+        LfsPointer::read(&staging.path().join(&lock_name)),
+    ) else {
+        return Err(AppError::BundleError(
+            "The bundle has no model with a lock file".to_string(),
+        ));
+    };
+    if model.sha256 != pointer.sha256 || model.size != pointer.size {
+        return Err(AppError::BundleError(
+            "The bundled model does not match its lock file".to_string(),
+        ));
+    }
+
+    // Check every target before changing anything
+    let mut installs = Vec::new();
+    let mut conflicts = Vec::new();
+    for file in &manifest.files {
+        if file.path == model_name || file.path == lock_name {
+            continue;
+        }
+        let target = dest.join(safe_path(&file.path)?);
+        let unchanged = target.is_file() && sha256_file(&target, None, None)? == file.sha256;
+        if unchanged {
+            println!("  {} is up to date", target.display());
+        } else if target.exists() && !force {
+            conflicts.push(target.display().to_string());
+        } else {
+            installs.push((staging.path().join(&file.path), target));
+        }
+    }
+    if !conflicts.is_empty() {
+        return Err(AppError::BundleError(format!(
+            "These files differ from the bundled ones, use --force to replace them: {}",
+            conflicts.join(", ")
+        )));
+    }
+
+    install_model(
+        config,
+        &staging.path().join(&model_name),
+        &staging.path().join(&lock_name),
+        model,
+        manifest.model.clone(),
+        archive,
+    )?;
+    for (source, target) in installs {
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(&source, &target).map_err(|e| {
+            AppError::FileWriteError(format!("Failed to write '{}': {}", target.display(), e))
+        })?;
+        println!("  Installed {}", target.display());
+    }
+
+    println!(
+        "{}",
+        style(format!("Bundle {} imported", archive.display())).green()
+    );
+    Ok(())
+}
+
+fn install_model(
+    config: &Config,
+    model: &Path,
+    lock: &Path,
+    file: &BundleFile,
+    provenance: Option<Manifest>,
+    archive: &Path,
+) -> Result<(), AppError> {
+    let target = Path::new(&config.cache_dir).join(LLAMAFILE_NAME);
+    let _lock = FileLock::exclusive(&target)?;
+    if target.is_file() && lock_path(&target).is_file() {
+        let size = std::fs::metadata(&target)?.len();
+        if let (Some(sha256), Status::Verified) = verify_model(&target, size)? {
+            if sha256 == file.sha256 && size == file.size {
+                println!("  {} is up to date", target.display());
+                return Ok(());
+            }
+        }
+    }
+
+    let mut perms = std::fs::metadata(model)?.permissions();
+    perms.set_mode(0o755);
+    std::fs::set_permissions(model, perms)?;
+    std::fs::rename(lock, lock_path(&target))?;
+    std::fs::rename(model, &target)?;
+    provenance
+        .unwrap_or_else(|| Manifest {
+            url: archive.display().to_string(),
+            sha256: file.sha256.clone(),
+            size: file.size,
+            downloaded_at: chrono::Utc::now().to_rfc3339(),
+            llamafile_version: LLAMAFILE_VERSION.to_string(),
+        })
+        .write(&manifest_path(&target))?;
+    record_sha256(&target, &file.sha256);
+    println!("  Installed {}", target.display());
+    Ok(())
+}
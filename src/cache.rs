@@ -0,0 +1,652 @@
+use crate::lock::{FileLock, GUARD_SUFFIX};
+use crate::models::{AppError, Config};
+use crate::result_cache::RESULTS_DIR;
+use crate::report::render_table;
+use console::style;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// File name of the Flow-Judge llamafile in the cache directory.
+pub const LLAMAFILE_NAME: &str = "flow-judge.llamafile";
+/// Files below this size are always hashed when listing the cache; hashing a
+/// larger model shows a progress bar.
+const HASH_PROGRESS_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// What a file in the cache directory is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Model,
+    Lock,
+    /// The cached hash of a model.
+    Checksum,
+    /// Where and when a model was downloaded.
+    Manifest,
+    /// An interrupted download, resumed by the next one.
+    Partial,
+    /// Judgments reused by later runs.
+    Results,
+    RunData,
+    Other,
+}
+
+impl ArtifactKind {
+    fn label(self) -> &'static str {
+        match self {
+            ArtifactKind::Model => "model",
+            ArtifactKind::Lock => "lock",
+            ArtifactKind::Checksum => "checksum",
+            ArtifactKind::Manifest => "manifest",
+            ArtifactKind::Partial => "partial download",
+            ArtifactKind::Results => "cached results",
+            ArtifactKind::RunData => "run data",
+            ArtifactKind::Other => "other",
+        }
+    }
+}
+
+/// Outcome of checking an artifact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// The model matches the hash and size in its lock file.
+    Verified,
+    /// The model is shorter than its lock file says.
+    Incomplete,
+    Mismatch,
+    /// A model without a lock file to check it against.
+    NoLock,
+    /// A lock file that is not a Git LFS pointer.
+    InvalidLock,
+    /// A model another process is downloading or verifying right now.
+    Busy,
+    Ok,
+}
+
+impl Status {
+    fn label(&self) -> String {
+        match self {
+            Status::Verified => style("verified").green().to_string(),
+            Status::Incomplete => style("incomplete").red().to_string(),
+            Status::Mismatch => style("hash mismatch").red().to_string(),
+            Status::NoLock => style("no lock file").yellow().to_string(),
+            Status::InvalidLock => style("invalid").red().to_string(),
+            Status::Busy => style("in use").yellow().to_string(),
+            Status::Ok => "ok".to_string(),
+        }
+    }
+
+    fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            Status::Incomplete | Status::Mismatch | Status::InvalidLock
+        )
+    }
+}
+
+/// A file or directory in the cache directory.
+#[derive(Debug)]
+pub struct Artifact {
+    pub path: PathBuf,
+    pub kind: ArtifactKind,
+    pub size: u64,
+    pub modified: SystemTime,
+    pub sha256: Option<String>,
+    pub status: Status,
+}
+
+impl Artifact {
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// The `oid sha256:` and `size` lines of a Git LFS pointer file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LfsPointer {
+    pub sha256: String,
+    pub size: u64,
+}
+
+impl LfsPointer {
+    pub fn parse(content: &str) -> Option<Self> {
+        let mut sha256 = None;
+        let mut size = None;
+        for line in content.lines() {
+            if let Some(oid) = line.strip_prefix("oid sha256:") {
+                sha256 = Some(oid.trim().to_ascii_lowercase());
+            } else if let Some(value) = line.strip_prefix("size ") {
+                size = value.trim().parse().ok();
+            }
+        }
+        Some(Self {
+            sha256: sha256.filter(|oid| oid.len() == 64)?,
+            size: size?,
+        })
+    }
+
+    pub fn read(path: &Path) -> Option<Self> {
+        Self::parse(&std::fs::read_to_string(path).ok()?)
+    }
+}
+
+/// Streams a file through SHA-256, hashing at most `limit` bytes.
+pub fn sha256_file(
+    path: &Path,
+    limit: Option<u64>,
+    progress: Option<&ProgressBar>,
+) -> Result<String, AppError> {
+    let file = File::open(path).map_err(|e| {
+        AppError::FileReadError(format!("Failed to open '{}': {}", path.display(), e))
+    })?;
+    let mut reader = file.take(limit.unwrap_or(u64::MAX));
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        if let Some(progress) = progress {
+            progress.inc(read as u64);
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// A hash computed earlier, valid while the file keeps its size and mtime.
+#[derive(Debug, Serialize, Deserialize)]
+struct HashRecord {
+    size: u64,
+    modified_ns: u64,
+    sha256: String,
+}
+
+/// Where the hash of a model is cached.
+pub fn checksum_path(model: &Path) -> PathBuf {
+    let mut name = model.as_os_str().to_owned();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+/// Size and modification time of a file, in nanoseconds since the epoch.
+fn file_stamp(path: &Path) -> Result<(u64, u64), AppError> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((
+        metadata.len(),
+        u64::try_from(modified.as_nanos()).unwrap_or(u64::MAX),
+    ))
+}
+
+/// Hashes a file like [`sha256_file`], reusing the hash cached next to it
+/// while the file keeps its size and modification time.
+pub fn cached_sha256(path: &Path) -> Result<String, AppError> {
+    let (size, modified_ns) = file_stamp(path)?;
+    let record = std::fs::read_to_string(checksum_path(path))
+        .ok()
+        .and_then(|content| serde_json::from_str::<HashRecord>(&content).ok());
+    if let Some(record) = record {
+        if record.size == size && record.modified_ns == modified_ns {
+            debug!("Using cached hash of {}", path.display());
+            return Ok(record.sha256);
+        }
+    }
+
+    let sha256 = hash_with_progress(path, size)?;
+    record_sha256(path, &sha256);
+    Ok(sha256)
+}
+
+/// Caches `sha256` as the hash of the current contents of `path`. Failing
+/// to write the cache only costs hashing again next time.
+pub fn record_sha256(path: &Path, sha256: &str) {
+    let write = || -> Result<(), AppError> {
+        let (size, modified_ns) = file_stamp(path)?;
+        let record = HashRecord {
+            size,
+            modified_ns,
+            sha256: sha256.to_string(),
+        };
+        // Replaced atomically, as processes verifying the model share its lock
+        crate::write_atomic(&checksum_path(path).to_string_lossy(), |file| {
+            serde_json::to_writer_pretty(file, &record).map_err(AppError::from)
+        })
+    };
+    if let Err(e) = write() {
+        debug!("Could not cache the hash of {}: {}", path.display(), e);
+    }
+}
+
+/// Checks a model against its lock file: it must have exactly the locked
+/// size and SHA-256.
+///
+/// Any bytes after the locked size are a mismatch, so a cache that still has
+/// the completion marker of earlier versions appended must be migrated with
+/// [`strip_legacy_trailer`] first. Hashes are cached with the size and mtime
+/// of the model, so an unchanged model is hashed once.
+pub fn verify_model(path: &Path, size: u64) -> Result<(Option<String>, Status), AppError> {
+    let Some(pointer) = LfsPointer::read(&lock_path(path)) else {
+        let sha256 = cached_sha256(path)?;
+        return Ok((Some(sha256), Status::NoLock));
+    };
+    if size < pointer.size {
+        return Ok((None, Status::Incomplete));
+    }
+    if size > pointer.size {
+        return Ok((None, Status::Mismatch));
+    }
+    let sha256 = cached_sha256(path)?;
+    let status = if sha256 == pointer.sha256 {
+        Status::Verified
+    } else {
+        Status::Mismatch
+    };
+    Ok((Some(sha256), status))
+}
+
+/// Hashes like [`sha256_file`], showing a progress bar for large files.
+pub fn hash_with_progress(path: &Path, size: u64) -> Result<String, AppError> {
+    if size < HASH_PROGRESS_THRESHOLD {
+        return sha256_file(path, None, None);
+    }
+    let progress = ProgressBar::new(size);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{msg} [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} {eta}")
+            .unwrap()
+            .progress_chars("━━╾─"),
+    );
+    progress.set_message(format!(
+        "Hashing {}",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let sha256 = sha256_file(path, None, Some(&progress));
+    progress.finish_and_clear();
+    sha256
+}
+
+/// Provenance of a downloaded model, kept next to it in the cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub url: String,
+    pub sha256: String,
+    pub size: u64,
+    /// When the download completed, in RFC 3339.
+    pub downloaded_at: String,
+    pub llamafile_version: String,
+}
+
+impl Manifest {
+    pub fn read(path: &Path) -> Option<Self> {
+        serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), AppError> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// The manifest belonging to a model.
+pub fn manifest_path(model: &Path) -> PathBuf {
+    let mut name = model.as_os_str().to_owned();
+    name.push(".manifest.json");
+    PathBuf::from(name)
+}
+
+/// Removes the `{"download_complete":true,...}` marker that earlier versions
+/// appended to a downloaded model, returning the timestamp it recorded.
+///
+/// Only a marker directly after the `size` bytes of the lock file is
+/// removed; any other trailing data is left in place, and
+/// [`verify_model`] rejects the model as a mismatch.
+pub fn strip_legacy_trailer(path: &Path, size: u64) -> Result<Option<String>, AppError> {
+    let length = std::fs::metadata(path)?.len();
+    if length <= size || length - size > 1024 {
+        return Ok(None);
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+    file.seek(SeekFrom::Start(size))?;
+    let mut trailer = Vec::new();
+    file.read_to_end(&mut trailer)?;
+    let Ok(marker) = serde_json::from_slice::<serde_json::Value>(&trailer) else {
+        return Ok(None);
+    };
+    if marker.get("download_complete") != Some(&serde_json::Value::Bool(true)) {
+        return Ok(None);
+    }
+
+    file.set_len(size)?;
+    info!(
+        "Removed the completion marker appended to {}",
+        path.display()
+    );
+    Ok(Some(
+        marker
+            .get("timestamp")
+            .and_then(serde_json::Value::as_str)
+            .map_or_else(|| chrono::Utc::now().to_rfc3339(), str::to_string),
+    ))
+}
+
+/// The lock file belonging to a model.
+pub fn lock_path(model: &Path) -> PathBuf {
+    let mut name = model.as_os_str().to_owned();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+fn kind_of(path: &Path) -> ArtifactKind {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if name.ends_with(".llamafile") {
+        ArtifactKind::Model
+    } else if name.ends_with(".lock") {
+        ArtifactKind::Lock
+    } else if name.ends_with(".sha256") {
+        ArtifactKind::Checksum
+    } else if name.ends_with(".manifest.json") {
+        ArtifactKind::Manifest
+    } else if name.ends_with(".part") {
+        ArtifactKind::Partial
+    } else if name == RESULTS_DIR {
+        ArtifactKind::Results
+    } else if name == "last_result.txt" {
+        ArtifactKind::RunData
+    } else {
+        ArtifactKind::Other
+    }
+}
+
+fn disk_usage(path: &Path) -> Result<u64, AppError> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut total = 0;
+    for entry in std::fs::read_dir(path)? {
+        total += disk_usage(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+/// Lists the cache directory, verifying models against their lock files.
+pub fn scan(cache_dir: &Path) -> Result<Vec<Artifact>, AppError> {
+    if !cache_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(cache_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    // Guard files must outlive the locks taken on them, so they are not artifacts
+    paths.retain(|path| !path.to_string_lossy().ends_with(GUARD_SUFFIX));
+    paths.sort();
+
+    let mut artifacts = Vec::with_capacity(paths.len());
+    for path in paths {
+        let metadata = std::fs::metadata(&path)?;
+        let kind = kind_of(&path);
+        let size = disk_usage(&path)?;
+        let (sha256, status) = match kind {
+            ArtifactKind::Model => match FileLock::try_shared(&path)? {
+                Some(_lock) => verify_model(&path, size)?,
+                None => (None, Status::Busy),
+            },
+            _ if metadata.is_file() && size < HASH_PROGRESS_THRESHOLD => {
+                let sha256 = sha256_file(&path, None, None)?;
+                let status = if kind == ArtifactKind::Lock && LfsPointer::read(&path).is_none() {
+                    Status::InvalidLock
+                } else {
+                    Status::Ok
+                };
+                (Some(sha256), status)
+            }
+            _ => (None, Status::Ok),
+        };
+        artifacts.push(Artifact {
+            path,
+            kind,
+            size,
+            modified: metadata.modified()?,
+            sha256,
+            status,
+        });
+    }
+    Ok(artifacts)
+}
+
+fn print_artifacts(cache_dir: &Path, artifacts: &[Artifact]) {
+    println!(
+        "\n{}",
+        style(format!("Cache directory: {}", cache_dir.display()))
+            .yellow()
+            .bold()
+    );
+    if artifacts.is_empty() {
+        println!("{}", style("The cache is empty.").dim());
+        return;
+    }
+
+    let headers = ["Artifact", "Kind", "Size", "Modified", "SHA-256", "Status"].map(str::to_string);
+    let rows: Vec<Vec<String>> = artifacts
+        .iter()
+        .map(|artifact| {
+            vec![
+                artifact.name(),
+                artifact.kind.label().to_string(),
+                HumanBytes(artifact.size).to_string(),
+                chrono::DateTime::<chrono::Local>::from(artifact.modified)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string(),
+                artifact
+                    .sha256
+                    .as_deref()
+                    .map_or_else(|| "-".to_string(), |sha256| sha256[..12].to_string()),
+                artifact.status.label(),
+            ]
+        })
+        .collect();
+    print!("{}", render_table(&headers, &rows, false));
+    println!(
+        "Total: {}",
+        HumanBytes(artifacts.iter().map(|artifact| artifact.size).sum())
+    );
+}
+
+/// Runs `fwj cache list`.
+pub fn list(config: &Config) -> Result<(), AppError> {
+    let cache_dir = Path::new(&config.cache_dir);
+    print_artifacts(cache_dir, &scan(cache_dir)?);
+    Ok(())
+}
+
+/// Runs `fwj cache verify`, failing if any model or lock file is broken.
+pub fn verify(config: &Config) -> Result<(), AppError> {
+    let cache_dir = Path::new(&config.cache_dir);
+    let artifacts = scan(cache_dir)?;
+    print_artifacts(cache_dir, &artifacts);
+
+    let failures = artifacts
+        .iter()
+        .filter(|artifact| artifact.status.is_failure())
+        .count();
+    if failures > 0 {
+        return Err(AppError::CustomError(format!(
+            "{} cached artifact(s) failed verification; remove them with `fwj cache clean --stale`",
+            failures
+        )));
+    }
+    Ok(())
+}
+
+/// What `fwj cache clean` removes.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Default)]
+pub struct CleanOptions {
+    /// Models that are not configured or fail verification, and orphaned lock files.
+    pub stale: bool,
+    pub models: bool,
+    pub run_data: bool,
+    pub results: bool,
+    pub all: bool,
+    /// Only remove artifacts that were not modified for this many days.
+    pub older_than: Option<u64>,
+    pub dry_run: bool,
+}
+
+/// The models the configuration refers to, which are never stale.
+fn configured_models(config: &Config) -> Vec<PathBuf> {
+    let mut models = vec![Path::new(&config.cache_dir).join(LLAMAFILE_NAME)];
+    let overrides = std::iter::once(&config.run)
+        .chain(config.tasks.iter().map(|task| &task.run))
+        .chain(config.profiles.values().map(|profile| &profile.run));
+    models.extend(
+        overrides
+            .filter_map(|run| run.model.as_ref())
+            .map(PathBuf::from),
+    );
+    models
+        .into_iter()
+        .map(|path| path.canonicalize().unwrap_or(path))
+        .collect()
+}
+
+/// Why an artifact would be removed, if it is selected by the options.
+fn removal_reason(
+    artifact: &Artifact,
+    configured: &[PathBuf],
+    options: &CleanOptions,
+) -> Option<&'static str> {
+    if let Some(days) = options.older_than {
+        let cutoff = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+        if artifact.modified > cutoff {
+            return None;
+        }
+    }
+
+    let path = artifact
+        .path
+        .canonicalize()
+        .unwrap_or_else(|_| artifact.path.clone());
+    match artifact.kind {
+        _ if options.all => Some("all"),
+        ArtifactKind::Model
+        | ArtifactKind::Lock
+        | ArtifactKind::Checksum
+        | ArtifactKind::Manifest
+        | ArtifactKind::Partial
+            if options.models =>
+        {
+            Some("model")
+        }
+        ArtifactKind::RunData if options.run_data => Some("run data"),
+        ArtifactKind::Results if options.results => Some("cached results"),
+        ArtifactKind::Model if options.stale && artifact.status.is_failure() => {
+            Some("failed verification")
+        }
+        ArtifactKind::Model if options.stale && !configured.contains(&path) => {
+            Some("not configured")
+        }
+        ArtifactKind::Lock if options.stale && artifact.status.is_failure() => Some("invalid"),
+        ArtifactKind::Lock if options.stale => {
+            (!path.with_extension("").exists()).then_some("orphaned lock")
+        }
+        ArtifactKind::Checksum if options.stale => {
+            (!path.with_extension("").exists()).then_some("orphaned checksum")
+        }
+        ArtifactKind::Manifest if options.stale => {
+            let model = path.with_extension("").with_extension("");
+            (!model.exists()).then_some("orphaned manifest")
+        }
+        _ => None,
+    }
+}
+
+/// The file whose lock guards an artifact: the model for its lock file,
+/// cached hash, manifest and partial download, otherwise the artifact itself.
+fn owner(artifact: &Artifact) -> PathBuf {
+    match artifact.kind {
+        ArtifactKind::Lock | ArtifactKind::Checksum | ArtifactKind::Partial => {
+            artifact.path.with_extension("")
+        }
+        ArtifactKind::Manifest => artifact.path.with_extension("").with_extension(""),
+        _ => artifact.path.clone(),
+    }
+}
+
+/// Runs `fwj cache clean`.
+pub fn clean(config: &Config, options: &CleanOptions) -> Result<(), AppError> {
+    let cache_dir = Path::new(&config.cache_dir);
+    let configured = configured_models(config);
+    let mut freed = 0;
+    let mut removed = 0;
+
+    for artifact in scan(cache_dir)? {
+        let Some(reason) = removal_reason(&artifact, &configured, options) else {
+            continue;
+        };
+        let verb = if options.dry_run {
+            "Would remove"
+        } else {
+            let Some(_lock) = FileLock::try_exclusive(&owner(&artifact))? else {
+                println!(
+                    "Skipped {} (in use by another fwj process)",
+                    artifact.name()
+                );
+                continue;
+            };
+            info!("Removing {} ({})", artifact.path.display(), reason);
+            if artifact.path.is_dir() {
+                std::fs::remove_dir_all(&artifact.path)?;
+            } else {
+                std::fs::remove_file(&artifact.path)?;
+            }
+            "Removed"
+        };
+        println!(
+            "{} {} ({}, {})",
+            verb,
+            artifact.name(),
+            reason,
+            HumanBytes(artifact.size)
+        );
+        freed += artifact.size;
+        removed += 1;
+    }
+
+    if removed == 0 {
+        println!("{}", style("Nothing to remove.").dim());
+    } else {
+        println!(
+            "{}",
+            style(format!(
+                "{} {} in {} artifact(s)",
+                if options.dry_run {
+                    "Would free"
+                } else {
+                    "Freed"
+                },
+                HumanBytes(freed),
+                removed
+            ))
+            .green()
+        );
+    }
+    Ok(())
+}
@@ -1,13 +1,20 @@
+use crate::models::{BackupMode, RunConfig, SelectionConfig};
+use clap::builder::RangedU64ValueParser;
 use clap::{Parser, Subcommand};
 use log::LevelFilter;
 
 /// CLI tool for processing tasks
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Args {
-    /// Path to the data file or "fetch" to download
-    #[arg(short, long, default_value = "fetch")]
-    pub data: String,
+    /// Path to the data file or "fetch" to download (replaces the tasks in the config file)
+    #[arg(short, long)]
+    pub data: Option<String>,
+
+    /// Write the judged dataset here instead of updating the data file in place
+    #[arg(short, long, requires = "data")]
+    pub output: Option<String>,
 
     /// Optional config file path
     #[arg(long, default_value = "config.yaml")]
@@ -21,38 +28,98 @@ pub struct Args {
     #[arg(long, default_value = "error")]
     pub log_level: LevelFilter,
 
-    /// Path to the rubric Jinja template or "fetch" to download
-    #[arg(short, long, default_value = "fetch")]
-    pub rubric: String,
+    /// Path to the rubric Jinja template or "fetch" to download (replaces the tasks in the config file)
+    #[arg(short, long)]
+    pub rubric: Option<String>,
 
     /// Display the last result
     #[arg(short = 'l', long, default_value = "false")]
     pub last_result: bool,
 
-    /// Set the concurrent batch size
-    #[arg(short = 'b', long, default_value = "1")]
-    pub batch_size: usize,
+    /// Set the concurrent batch size [default: 1]
+    #[arg(short = 'b', long)]
+    pub batch_size: Option<usize>,
+
+    /// Judge only the first N items
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Judge a random sample of N items
+    #[arg(long)]
+    pub sample: Option<usize>,
+
+    /// Seed for --sample, to reproduce a sample
+    #[arg(long)]
+    pub sample_seed: Option<u64>,
+
+    /// Stratify --sample on this field
+    #[arg(long)]
+    pub stratify_by: Option<String>,
+
+    /// Judge only items matching a field filter (e.g. "category == billing && score == ''")
+    #[arg(long)]
+    pub filter: Option<String>,
+
+    /// Judge only shard i/n of the dataset (1-based, e.g. "2/4")
+    #[arg(long)]
+    pub shard: Option<String>,
+
+    /// Never wait for input: skip the welcome notice and other prompts
+    #[arg(short = 'y', long)]
+    pub yes: bool,
 
-    /// Context size for llamafile
-    #[arg(short, long, default_value = "8192")]
-    pub context_size: usize,
+    /// Only use cached artifacts and fail instead of downloading anything
+    #[arg(long)]
+    pub offline: bool,
 
-    /// GPU layers for llamafile
-    #[arg(long, default_value = "34")]
-    pub gpu_layers: usize,
+    /// Judge every item, without reusing or storing cached judgments
+    #[arg(long, conflicts_with = "refresh")]
+    pub no_cache: bool,
 
-    /// Temperature for llamafile
-    #[arg(long = "temp", default_value = "0.1")]
-    pub temperature: f32,
+    /// Judge every item again and replace the cached judgments
+    #[arg(long)]
+    pub refresh: bool,
 
-    /// Max tokens for llamafile
-    #[arg(short = 'n', long, default_value = "1000")]
-    pub max_tokens: usize,
+    /// Named profile from the config file to apply
+    #[arg(short = 'p', long)]
+    pub profile: Option<String>,
 
-    /// Thread count for llamafile (default: number of available threads)
-    #[arg(short = 't', long)]
+    /// Path to a llamafile to use instead of the downloaded Flow-Judge model
+    #[arg(short, long)]
+    pub model: Option<String>,
+
+    /// Context size for llamafile [default: 8192]
+    #[arg(short, long)]
+    pub context_size: Option<usize>,
+
+    /// GPU layers for llamafile [default: 34]
+    #[arg(long)]
+    pub gpu_layers: Option<usize>,
+
+    /// Temperature for llamafile [default: 0.1]
+    #[arg(long = "temp")]
+    pub temperature: Option<f64>,
+
+    /// Max tokens for llamafile [default: 1000]
+    #[arg(short = 'n', long)]
+    pub max_tokens: Option<usize>,
+
+    /// Threads per llamafile process (default: the available threads split across the batch)
+    #[arg(
+        short = 't',
+        long,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
     pub thread_count: Option<usize>,
 
+    /// Sampling seed for llamafile, for reproducible judgments
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Seconds each item may take before its llamafile is killed and the item marked as timed out
+    #[arg(long, value_name = "SECS")]
+    pub timeout: Option<f64>,
+
     /// Additional llamafile arguments as key-value pairs (e.g., "key1=value1,key2=value2")
     #[arg(short = 'a', long)]
     pub llamafile_kvargs: Option<String>,
@@ -61,10 +128,48 @@ pub struct Args {
     #[arg(long)]
     pub disable_kv_offload: bool,
 
+    /// Also record runs, items and judgments into this `SQLite` database
+    #[arg(long)]
+    pub results_db: Option<String>,
+
+    /// Back up each dataset before it is modified in place
+    #[arg(long, value_enum)]
+    pub backup: Option<BackupMode>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+impl Args {
+    /// The sampling and llamafile options given explicitly on the command line.
+    pub fn run_config(&self) -> RunConfig {
+        RunConfig {
+            model: self.model.clone(),
+            context_size: self.context_size,
+            gpu_layers: self.gpu_layers,
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            thread_count: self.thread_count,
+            llamafile_kvargs: self.llamafile_kvargs.clone(),
+            disable_kv_offload: self.disable_kv_offload.then_some(true),
+            seed: self.seed,
+            timeout: self.timeout,
+        }
+    }
+
+    /// The item selection given explicitly on the command line.
+    pub fn selection_config(&self) -> SelectionConfig {
+        SelectionConfig {
+            limit: self.limit,
+            sample: self.sample,
+            sample_seed: self.sample_seed,
+            stratify_by: self.stratify_by.clone(),
+            filter: self.filter.clone(),
+            shard: self.shard.clone(),
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Generate auto-completions
@@ -75,6 +180,210 @@ pub enum Commands {
         #[arg(short, long)]
         output: Option<String>,
     },
+    /// Inspect the layered configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Judge a single input/output pair and print the feedback and score
+    Judge {
+        /// Path to the rubric Jinja template
+        #[arg(short, long)]
+        rubric: String,
+        /// The input given to the system under evaluation
+        #[arg(long, required_unless_present = "input_file", conflicts_with = "input_file")]
+        input: Option<String>,
+        /// Read the input from a file ("-" for stdin)
+        #[arg(long)]
+        input_file: Option<String>,
+        /// The output to judge
+        #[arg(long, required_unless_present = "output_file", conflicts_with = "output_file")]
+        output: Option<String>,
+        /// Read the output from a file ("-" for stdin)
+        #[arg(long)]
+        output_file: Option<String>,
+    },
+    /// Inspect and prune the cache directory
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+    /// Measure judging throughput at different batch sizes and thread counts and recommend settings
+    Bench {
+        /// Rubric Jinja template to render the items with [default: the fetched default rubric]
+        #[arg(short, long)]
+        rubric: Option<String>,
+        /// Data file with the items to judge [default: the fetched default dataset]
+        #[arg(short, long)]
+        data: Option<String>,
+        /// Number of items judged with each configuration
+        #[arg(long, default_value = "8")]
+        items: usize,
+        /// Batch sizes to try, comma-separated [default: 1,2,4,8 up to the available threads]
+        #[arg(long, value_delimiter = ',')]
+        batch_sizes: Vec<usize>,
+        /// Threads per llamafile process to try, comma-separated [default: the even split of the available threads and half of it]
+        #[arg(long, value_delimiter = ',')]
+        threads: Vec<usize>,
+    },
+    /// Move the judge to machines without network access
+    Bundle {
+        #[command(subcommand)]
+        action: BundleCommand,
+    },
+    /// Combine partially judged copies of one dataset, e.g. the outputs of separate shards
+    Merge {
+        /// Judged JSON or CSV files to combine
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Where to write the merged dataset
+        #[arg(short, long)]
+        output: String,
+        /// Field with a stable item id; items without one are matched by a hash of input and output
+        #[arg(long, default_value = "id")]
+        id_field: String,
+        /// What to do with items that were given different scores in different files
+        #[arg(long, value_enum, default_value = "fail")]
+        on_conflict: OnConflict,
+        /// Fail if any item is not judged in any of the files
+        #[arg(long)]
+        require_complete: bool,
+    },
+    /// Print aggregate statistics of a judged dataset
+    Report {
+        /// Judged JSON or CSV file
+        file: String,
+        /// Break the statistics down by the values of this field
+        #[arg(long)]
+        by: Option<String>,
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Number of bootstrap resamples for the confidence intervals
+        #[arg(long, default_value = "1000")]
+        resamples: usize,
+        /// Confidence level of the intervals
+        #[arg(long, default_value = "0.95")]
+        confidence: f64,
+        /// Seed for the bootstrap resampling
+        #[arg(long, default_value = "0")]
+        seed: u64,
+    },
+    /// Compare the scores of two judged runs of the same dataset
+    Diff {
+        /// The judged file of the earlier run
+        old: String,
+        /// The judged file of the later run
+        new: String,
+        /// Field with a stable item id; items without one are matched by a hash of their input
+        #[arg(long, default_value = "id")]
+        id_field: String,
+        /// Number of regressions to show with their feedback
+        #[arg(long, default_value = "10")]
+        top: usize,
+        /// List every item whose score changed
+        #[arg(long)]
+        all: bool,
+    },
+    /// Measure how well the judge scores agree with human labels
+    MetaEval {
+        /// Judged JSON or CSV file with human labels
+        file: String,
+        /// Field holding the human scores
+        #[arg(long, default_value = "human_score")]
+        human_column: String,
+        /// Field holding the judge scores
+        #[arg(long, default_value = "score")]
+        judge_column: String,
+        /// Number of disagreements to list
+        #[arg(long, default_value = "10")]
+        top: usize,
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        format: ReportFormat,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Table,
+    Json,
+    Markdown,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Report the conflicts and write nothing
+    Fail,
+    /// Keep the judgment from the first file that has one
+    First,
+    /// Keep the judgment from the last file that has one
+    Last,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the configuration assembled from defaults, config files, FWJ_* variables and flags
+    Show {
+        /// Print the final value of every setting and the layer it came from
+        #[arg(long)]
+        resolved: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// List the cached artifacts with their size, hash and verification status
+    List,
+    /// Verify the cached models against their lock files
+    Verify,
+    /// Remove cached artifacts (stale ones unless told otherwise)
+    Clean {
+        /// Remove models that are not configured or fail verification, and orphaned lock files
+        #[arg(long)]
+        stale: bool,
+        /// Remove all models with their lock files, cached hashes and partial downloads
+        #[arg(long)]
+        models: bool,
+        /// Remove data left by previous runs
+        #[arg(long)]
+        run_data: bool,
+        /// Remove the cached judgments
+        #[arg(long)]
+        results: bool,
+        /// Remove everything in the cache directory
+        #[arg(long)]
+        all: bool,
+        /// Only remove artifacts not modified in this many days
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u64>,
+        /// Show what would be removed without removing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the cache directory
+    Path,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BundleCommand {
+    /// Pack the verified model, rubric templates, config and a manifest of their hashes into one archive
+    Export {
+        /// Archive to write (.tar.gz, .tgz, .tar or .zip)
+        #[arg(short, long, default_value = "fwj-bundle.tar.gz")]
+        output: String,
+    },
+    /// Unpack and verify a bundle, installing the model into the cache directory
+    Import {
+        /// Archive written by `fwj bundle export`
+        archive: String,
+        /// Directory to install the rubric templates and config into
+        #[arg(long, default_value = ".")]
+        dest: String,
+        /// Replace rubric templates and config files that differ from the bundled ones
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -0,0 +1,302 @@
+use crate::cli::Args;
+use crate::models::{AppError, Config, RunConfig};
+use console::style;
+use indexmap::IndexMap;
+use log::info;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the environment variables that override config values.
+pub const ENV_PREFIX: &str = "FWJ_";
+
+/// Where a configuration value came from, from lowest to highest precedence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layer {
+    Default,
+    User(PathBuf),
+    Project(PathBuf),
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layer::Default => write!(f, "default"),
+            Layer::User(path) => write!(f, "user config ({})", path.display()),
+            Layer::Project(path) => write!(f, "project config ({})", path.display()),
+            Layer::Env(var) => write!(f, "environment ({})", var),
+            Layer::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// The merged configuration together with the layer each value came from.
+#[derive(Debug)]
+pub struct ResolvedConfig {
+    pub config: Config,
+    pub values: Map<String, Value>,
+    pub sources: IndexMap<String, Layer>,
+    pub layers: Vec<(Layer, Map<String, Value>)>,
+}
+
+impl Config {
+    pub fn from_file(path: &str) -> Result<Self, AppError> {
+        let value = read_config_value(path)?;
+        serde_json::from_value(value).map_err(|e| {
+            AppError::ConfigError(format!("Invalid config file {}: {}", path, e))
+        })
+    }
+}
+
+/// Reads a YAML, JSON or TOML config file into a generic value.
+pub fn read_config_value(path: &str) -> Result<Value, AppError> {
+    let config_str = std::fs::read_to_string(path).map_err(|e| {
+        AppError::ConfigError(format!("Failed to read config file {}: {}", path, e))
+    })?;
+
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+
+    let value: Value = match extension.as_deref() {
+        Some("yaml" | "yml") => serde_yml::from_str(&config_str).map_err(|e| {
+            AppError::ConfigError(format!("Failed to parse YAML config file {}: {}", path, e))
+        })?,
+        Some("json") => serde_json::from_str(&config_str).map_err(|e| {
+            AppError::ConfigError(format!("Failed to parse JSON config file {}: {}", path, e))
+        })?,
+        Some("toml") => toml::from_str(&config_str).map_err(|e| {
+            AppError::ConfigError(format!("Failed to parse TOML config file {}: {}", path, e))
+        })?,
+        _ => {
+            return Err(AppError::ConfigError(format!(
+                "Unsupported config file format: {}",
+                path
+            )))
+        }
+    };
+
+    match value {
+        Value::Object(_) => Ok(value),
+        // An empty YAML file parses as null
+        Value::Null => Ok(Value::Object(Map::new())),
+        _ => Err(AppError::ConfigError(format!(
+            "Config file {} must contain a mapping at the top level",
+            path
+        ))),
+    }
+}
+
+/// Renders a config value in the format of the config file at `path`, the
+/// counterpart of [`read_config_value`]. Comments of the original file are lost.
+pub fn render_config_value(path: &str, value: &Value) -> Result<String, AppError> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("yaml" | "yml") => Ok(serde_yml::to_string(value)?),
+        Some("json") => Ok(serde_json::to_string_pretty(value)? + "\n"),
+        Some("toml") => toml::to_string(value).map_err(|e| {
+            AppError::ConfigError(format!("Failed to write TOML config file {}: {}", path, e))
+        }),
+        _ => Err(AppError::ConfigError(format!(
+            "Unsupported config file format: {}",
+            path
+        ))),
+    }
+}
+
+/// Directory of the per-user config, `~/.config/fwj` unless `XDG_CONFIG_HOME` is set.
+pub fn user_config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("fwj"))
+}
+
+pub fn user_config_file() -> Option<PathBuf> {
+    let dir = user_config_dir()?;
+    ["config.toml", "config.yaml", "config.yml", "config.json"]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Builds the configuration from built-in defaults, the user config, the
+/// project config, `FWJ_*` environment variables and command line flags,
+/// each layer overriding the ones before it.
+pub fn load(args: &Args) -> Result<ResolvedConfig, AppError> {
+    let mut layers = vec![(Layer::Default, default_values()?)];
+
+    if let Some(path) = user_config_file() {
+        info!("Loading user configuration from {}", path.display());
+        layers.push((
+            Layer::User(path.clone()),
+            file_values(&path.to_string_lossy())?,
+        ));
+    }
+
+    if Path::new(&args.config).exists() {
+        info!("Loading configuration from {}", args.config);
+        layers.push((
+            Layer::Project(PathBuf::from(&args.config)),
+            file_values(&args.config)?,
+        ));
+    } else {
+        info!("No config file found at {}", args.config);
+    }
+
+    let known_keys: Vec<String> = layers[0].1.keys().cloned().collect();
+    for (var, key, value) in env_values(std::env::vars(), &known_keys) {
+        let mut map = Map::new();
+        map.insert(key, value);
+        layers.push((Layer::Env(var), map));
+    }
+
+    layers.push((Layer::Cli, cli_values(args)?));
+
+    resolve(layers)
+}
+
+/// Merges the layers in order; later layers replace whole top-level values.
+pub fn resolve(layers: Vec<(Layer, Map<String, Value>)>) -> Result<ResolvedConfig, AppError> {
+    let mut values = Map::new();
+    let mut sources = IndexMap::new();
+
+    for (layer, map) in &layers {
+        for (key, value) in map {
+            // Unset values never hide a lower layer, except for the defaults
+            if value.is_null() && *layer != Layer::Default {
+                continue;
+            }
+            values.insert(key.clone(), value.clone());
+            sources.insert(key.clone(), layer.clone());
+        }
+    }
+
+    let config = serde_json::from_value(Value::Object(values.clone()))
+        .map_err(|e| AppError::ConfigError(format!("Invalid configuration: {}", e)))?;
+
+    Ok(ResolvedConfig {
+        config,
+        values,
+        sources,
+        layers,
+    })
+}
+
+fn file_values(path: &str) -> Result<Map<String, Value>, AppError> {
+    // Validate the file on its own first so errors point at the right file
+    Config::from_file(path)?;
+    Ok(as_map(read_config_value(path)?))
+}
+
+fn default_values() -> Result<Map<String, Value>, AppError> {
+    let config = Config {
+        run: RunConfig::defaults(),
+        ..Config::default()
+    };
+    Ok(as_map(serde_json::to_value(config)?))
+}
+
+/// Maps `FWJ_<KEY>` variables onto known config keys. Values are parsed as
+/// JSON where possible, so numbers, booleans and lists keep their type.
+pub fn env_values<I>(vars: I, known_keys: &[String]) -> Vec<(String, String, Value)>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut values = Vec::new();
+    for (var, raw) in vars {
+        let Some(key) = var.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let key = key.to_ascii_lowercase();
+        if !known_keys.contains(&key) {
+            continue;
+        }
+        let value = serde_json::from_str(&raw).unwrap_or(Value::String(raw));
+        values.push((var, key, value));
+    }
+    values.sort_by(|a, b| a.0.cmp(&b.0));
+    values
+}
+
+fn cli_values(args: &Args) -> Result<Map<String, Value>, AppError> {
+    let mut map = as_map(serde_json::to_value(args.run_config())?);
+    map.extend(as_map(serde_json::to_value(args.selection_config())?));
+    if let Some(results_db) = &args.results_db {
+        map.insert("results_db".to_string(), Value::from(results_db.clone()));
+    }
+    if let Some(backup) = args.backup {
+        map.insert("backup".to_string(), serde_json::to_value(backup)?);
+    }
+    if args.yes {
+        map.insert("noninteractive".to_string(), Value::Bool(true));
+    }
+    if args.offline {
+        map.insert("offline".to_string(), Value::Bool(true));
+    }
+    if args.no_cache {
+        map.insert("no_cache".to_string(), Value::Bool(true));
+    }
+    if args.refresh {
+        map.insert("refresh".to_string(), Value::Bool(true));
+    }
+    if let Some(profile) = &args.profile {
+        map.insert("profile".to_string(), Value::from(profile.clone()));
+    }
+    Ok(map)
+}
+
+fn as_map(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+/// Prints the final values with the layer each one came from, or with
+/// `with_sources` unset, the values each layer sets.
+pub fn show(resolved: &ResolvedConfig, with_sources: bool) {
+    if with_sources {
+        let width = resolved.values.keys().map(String::len).max().unwrap_or(0);
+        for (key, value) in &resolved.values {
+            let source = resolved
+                .sources
+                .get(key)
+                .map_or_else(String::new, ToString::to_string);
+            println!(
+                "{:<width$}  {}  {}",
+                key,
+                format_value(value),
+                style(format!("# {}", source)).dim(),
+                width = width
+            );
+        }
+        return;
+    }
+
+    for (layer, map) in &resolved.layers {
+        let set: Vec<_> = map.iter().filter(|(_, value)| !value.is_null()).collect();
+        if set.is_empty() {
+            continue;
+        }
+        println!("{}", style(format!("[{}]", layer)).bold());
+        for (key, value) in set {
+            println!("  {} = {}", key, format_value(value));
+        }
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
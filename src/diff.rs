@@ -0,0 +1,264 @@
+use crate::merge::{keys_with, preview};
+use crate::models::{AppError, IoItem};
+use crate::selection::field_text;
+use crate::stats::{bootstrap_ci, mean, wilcoxon_signed_rank};
+use crate::store::sha256_hex;
+use console::style;
+use indexmap::IndexMap;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::BTreeMap;
+
+const RESAMPLES: usize = 1000;
+const CONFIDENCE: f64 = 0.95;
+/// Width of each feedback column when regressions are shown side by side.
+const COLUMN_WIDTH: usize = 48;
+
+/// One item scored in both runs.
+#[derive(Debug)]
+pub struct ItemChange {
+    pub key: String,
+    pub input: String,
+    pub old_score: i32,
+    pub new_score: i32,
+    pub old_feedback: String,
+    pub new_feedback: String,
+}
+
+impl ItemChange {
+    pub fn delta(&self) -> i32 {
+        self.new_score - self.old_score
+    }
+}
+
+/// Items of two runs of the same dataset, aligned by key.
+#[derive(Debug, Default)]
+pub struct Alignment {
+    /// Items scored in both runs, in the order of the old run.
+    pub changes: Vec<ItemChange>,
+    pub only_old: usize,
+    pub only_new: usize,
+    /// Items present in both runs but not scored in at least one of them.
+    pub unscored: usize,
+}
+
+/// The key items are aligned by: the `id_field` value, or a hash of the
+/// input, so runs over re-generated outputs still line up.
+fn input_key(item: &IoItem, id_field: &str) -> String {
+    match field_text(item, id_field).filter(|id| !id.is_empty()) {
+        Some(id) => format!("{}={}", id_field, id),
+        None => format!(
+            "input:{}",
+            &sha256_hex(item.input().unwrap_or_default().as_bytes())[..16]
+        ),
+    }
+}
+
+pub fn align(old: &[IoItem], new: &[IoItem], id_field: &str) -> Alignment {
+    let old_keys = keys_with(old, |item| input_key(item, id_field));
+    let mut new_items: IndexMap<String, &IoItem> = keys_with(new, |item| input_key(item, id_field))
+        .into_iter()
+        .zip(new)
+        .collect();
+
+    let mut alignment = Alignment::default();
+    for (key, old_item) in old_keys.into_iter().zip(old) {
+        let Some(new_item) = new_items.shift_remove(&key) else {
+            alignment.only_old += 1;
+            continue;
+        };
+        match (old_item.score(), new_item.score()) {
+            (Some(old_score), Some(new_score)) => alignment.changes.push(ItemChange {
+                key,
+                input: old_item.input().unwrap_or_default().to_string(),
+                old_score,
+                new_score,
+                old_feedback: old_item.feedback().unwrap_or_default().to_string(),
+                new_feedback: new_item.feedback().unwrap_or_default().to_string(),
+            }),
+            _ => alignment.unscored += 1,
+        }
+    }
+    alignment.only_new = new_items.len();
+    alignment
+}
+
+/// Runs `fwj diff`: compares the scores of two judged runs of a dataset.
+pub fn run(
+    old_file: &str,
+    new_file: &str,
+    id_field: &str,
+    top: usize,
+    all: bool,
+) -> Result<(), AppError> {
+    let old = crate::read_items(old_file)?;
+    let new = crate::read_items(new_file)?;
+    let alignment = align(&old, &new, id_field);
+
+    println!(
+        "\n{}",
+        style(format!("Comparing {} → {}", old_file, new_file))
+            .yellow()
+            .bold()
+    );
+    println!(
+        "  Matched items  {} ({} only in old, {} only in new, {} not scored in both)",
+        alignment.changes.len(),
+        alignment.only_old,
+        alignment.only_new,
+        alignment.unscored
+    );
+    if alignment.changes.is_empty() {
+        println!("{}", style("No items were scored in both runs.").yellow());
+        return Ok(());
+    }
+
+    print_score_shift(&alignment.changes);
+    print_histogram(&alignment.changes);
+
+    let mut changed: Vec<&ItemChange> = alignment
+        .changes
+        .iter()
+        .filter(|c| c.delta() != 0)
+        .collect();
+    if all && !changed.is_empty() {
+        println!("\n{}", style("Changed items:").bold());
+        for change in &changed {
+            println!(
+                "  {} → {} ({:+})  {}  {}",
+                change.old_score,
+                change.new_score,
+                change.delta(),
+                change.key,
+                style(preview(&change.input)).dim()
+            );
+        }
+    }
+
+    changed.sort_by_key(|change| change.delta());
+    let regressions: Vec<&&ItemChange> = changed
+        .iter()
+        .take_while(|change| change.delta() < 0)
+        .take(top)
+        .collect();
+    if !regressions.is_empty() {
+        println!("\n{}", style("Biggest regressions:").red().bold());
+        for change in regressions {
+            print_side_by_side(change);
+        }
+    }
+    Ok(())
+}
+
+/// Prints the mean scores, the mean change with its bootstrap interval and
+/// the Wilcoxon signed-rank test of the changes.
+fn print_score_shift(changes: &[ItemChange]) {
+    let old_scores: Vec<f64> = changes.iter().map(|c| f64::from(c.old_score)).collect();
+    let new_scores: Vec<f64> = changes.iter().map(|c| f64::from(c.new_score)).collect();
+    let deltas: Vec<f64> = changes.iter().map(|c| f64::from(c.delta())).collect();
+    let mut rng = StdRng::seed_from_u64(0);
+    let (low, high) = bootstrap_ci(&deltas, mean, RESAMPLES, CONFIDENCE, &mut rng);
+    println!(
+        "  Mean score     {:.2} → {:.2} (Δ {:+.2}, {:.0}% CI [{:+.2}, {:+.2}])",
+        mean(&old_scores),
+        mean(&new_scores),
+        mean(&deltas),
+        CONFIDENCE * 100.0,
+        low,
+        high
+    );
+
+    let improved = deltas.iter().filter(|d| **d > 0.0).count();
+    let regressed = deltas.iter().filter(|d| **d < 0.0).count();
+    println!(
+        "  Changed        {} improved, {} regressed, {} unchanged",
+        style(improved).green(),
+        style(regressed).red(),
+        deltas.len() - improved - regressed
+    );
+    match wilcoxon_signed_rank(&deltas) {
+        Some(test) => {
+            let verdict = if test.p_value < 1.0 - CONFIDENCE {
+                style("significant").bold()
+            } else {
+                style("not significant").dim()
+            };
+            println!(
+                "  Wilcoxon       W+ = {:.1}, z = {:.2}, p = {:.4} ({}, n = {})",
+                test.statistic, test.z, test.p_value, verdict, test.n
+            );
+        }
+        None => println!("  Wilcoxon       no score changed"),
+    }
+}
+
+fn print_histogram(changes: &[ItemChange]) {
+    let mut histogram: BTreeMap<i32, usize> = BTreeMap::new();
+    for change in changes {
+        *histogram.entry(change.delta()).or_default() += 1;
+    }
+    println!("\n{}", style("Score changes:").bold());
+    for (delta, count) in &histogram {
+        let label = format!("{:+}", delta);
+        let label = match delta.signum() {
+            1 => style(label).green(),
+            -1 => style(label).red(),
+            _ => style(label).dim(),
+        };
+        println!("  {:>4}  {}", label, count);
+    }
+}
+
+fn print_side_by_side(change: &ItemChange) {
+    println!(
+        "\n  {} {} → {}  {}",
+        style(&change.key).bold(),
+        change.old_score,
+        change.new_score,
+        style(preview(&change.input)).dim()
+    );
+    let old = wrap(&change.old_feedback, COLUMN_WIDTH);
+    let new = wrap(&change.new_feedback, COLUMN_WIDTH);
+    println!(
+        "  {} │ {}",
+        style(format!("{:<width$}", "Old feedback", width = COLUMN_WIDTH)).dim(),
+        style("New feedback").dim()
+    );
+    for line in 0..old.len().max(new.len()) {
+        println!(
+            "  {:<width$} │ {}",
+            old.get(line).map_or("", String::as_str),
+            new.get(line).map_or("", String::as_str),
+            width = COLUMN_WIDTH
+        );
+    }
+}
+
+/// Wraps text on whitespace into lines of at most `width` characters,
+/// breaking longer words.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            while !word.is_empty() {
+                let used = line.chars().count();
+                let space = usize::from(used > 0);
+                if used + space + word.len() <= width {
+                    if space == 1 {
+                        line.push(' ');
+                    }
+                    line.extend(word.drain(..));
+                } else if used > 0 {
+                    lines.push(std::mem::take(&mut line));
+                } else {
+                    line.extend(word.drain(..width));
+                    lines.push(std::mem::take(&mut line));
+                }
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
@@ -1,103 +1,196 @@
 use crate::AppError;
 use crate::Config;
 use console::style;
-use indicatif::{ProgressBar, ProgressStyle};
-use log::info;
-use reqwest::Client;
-use serde_json::{json, Value};
-use std::collections::HashMap;
+use indexmap::IndexSet;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use log::{info, warn};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::{Client, StatusCode};
+use sha2::{Digest, Sha256};
+use std::io::IsTerminal;
 use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs;
 use tokio::fs::File;
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
-use crate::models::LLAMAFILE_LOCK_URL;
-
-pub async fn download_flow_judge_llamafile(config: &Config) -> Result<(), AppError> {
-    println!(
-        "{}",
-        style(
-            "
-
-             F   L   O   W   A   I
-
-            ⣀⣀⣀⣀⣀⣀⣀⣀⣀⣀⣀⡀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀
-    ⠀⠀⠀⠀⠀⠀⠀⢸⣿⣿⣿⣿⣿⣿⣿⣿⣿⣿⣿⡿⠀⠀⢀⣀⡀⠀⠀⠀⠀⠀⠀⠀
-    ⠀⠀⠀⠀⠀⠀⠀⢸⣿⣿⣿⣿⣿⣿⣿⣿⣿⡿⠋⠀⢀⣴⣿⣿⡇⠀⠀⠀⠀⠀⠀⠀
-    ⠀⠀⠀⠀⠀⠀⠀⠈⠛⠛⠛⠛⠛⠛⠛⠛⠋⠀⢀⣴⣿⣿⣿⣿⡇⠀⠀⠀⠀⠀⠀⠀
-    ⠀⠀⠀⠀⠀⠀⠀⢀⣤⣤⣤⣤⡄⠀⠀⠀⢀⣴⣿⣿⣿⣿⣿⣿⡇⠀⠀⠀⠀⠀⠀⠀
-    ⠀⠀⠀⠀⠀⠀⠀⢸⣿⣿⣿⡿⠃⠀⢀⣴⣿⣿⣿⣿⣿⣿⣿⣿⡇⠀⠀⠀⠀⠀⠀⠀
-    ⠀⠀⠀⠀⠀⠀⠀⠸⣿⡿⠋⠀⠀⢰⣿⣿⣿⣿⣿⣿⣿⣿⣿⣿⡇⠀⠀⠀⠀⠀⠀⠀
-    ⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠉⠉⠉⠉⠉⠉⠉⠉⠉⠉⠀⠀⠀⠀⠀⠀⠀⠀
-    "
-        )
-        .white()
-    );
-
-    println!("{}", style("            --------------------------------------------------------------------------------").white().dim());
-
-    println!("{}", style("
-            Welcome friend.
-
-            This is a quick-start for the Flow-Judge-v0.1 model.
+use crate::cache::{
+    lock_path, manifest_path, record_sha256, sha256_file, strip_legacy_trailer, verify_model,
+    LfsPointer, Manifest, Status, LLAMAFILE_NAME,
+};
+use crate::lock::FileLock;
+use crate::source::{http_client, llamafile_source, lock_source, read_text, Source};
+
+/// Marker in the cache directory recording that the welcome notice was shown.
+const WELCOME_MARKER: &str = ".welcome_shown";
+
+/// Llamafile release the Flow-Judge model is packaged with.
+pub const LLAMAFILE_VERSION: &str = "v0.8.13";
+
+/// Retries of an interrupted llamafile download before giving up.
+const DOWNLOAD_RETRIES: u32 = 5;
+/// Wait before the first retry, doubled after every further failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Variable the Hugging Face tools read to stay off the network.
+const HF_OFFLINE_VAR: &str = "HF_HUB_OFFLINE";
+
+/// Whether to use only cached artifacts: `--offline`, `FWJ_OFFLINE` or the
+/// `HF_HUB_OFFLINE` variable shared with the Hugging Face tools.
+pub fn is_offline(config: &Config) -> bool {
+    config.offline
+        || std::env::var(HF_OFFLINE_VAR)
+            .is_ok_and(|value| !matches!(value.trim(), "" | "0" | "false"))
+}
 
-            This tool can evaluate 'input' and 'output' pairs from csv and json files.
+/// The cached files the Flow-Judge model needs that are not there.
+pub fn missing_llamafile_artifacts(config: &Config) -> Vec<String> {
+    let file_path = PathBuf::from(&config.cache_dir).join(LLAMAFILE_NAME);
+    let lock_file_path = lock_path(&file_path);
+    let mut missing = Vec::new();
+    if !file_path.is_file() {
+        missing.push(format!("Flow-Judge llamafile: {}", file_path.display()));
+    }
+    if LfsPointer::read(&lock_file_path).is_none() {
+        missing.push(format!("llamafile lock: {}", lock_file_path.display()));
+    }
+    missing
+}
 
-            The program will add columns 'score' and 'feedback' to the given file, editing it in-place.
+/// Fails with the full list of `missing` artifacts, if there are any.
+pub fn require_cached(missing: &[String]) -> Result<(), AppError> {
+    if missing.is_empty() {
+        return Ok(());
+    }
+    // Tasks sharing the default dataset or rubric would list it repeatedly
+    let missing: IndexSet<&String> = missing.iter().collect();
+    let list: Vec<String> = missing
+        .iter()
+        .map(|artifact| format!("  - {}", artifact))
+        .collect();
+    Err(AppError::OfflineError(format!(
+        "{} cached artifact(s) are missing:\n{}\nRun once with network access to fill the cache.",
+        missing.len(),
+        list.join("\n")
+    )))
+}
 
-            Before you begin you might want to read the instructions from the model card:
+/// Uses the cached llamafile without touching the network, verifying it
+/// against the lock file stored by the download. The caller holds the lock
+/// on the model.
+fn use_cached_llamafile(config: &Config) -> Result<(), AppError> {
+    require_cached(&missing_llamafile_artifacts(config))?;
 
-            https://huggingface.co/flowaicom/Flow-Judge-v0.1#prompt-format
-    ").white());
+    let file_path = PathBuf::from(&config.cache_dir).join(LLAMAFILE_NAME);
+    if let Some(pointer) = LfsPointer::read(&lock_path(&file_path)) {
+        migrate_legacy_cache(&file_path, &pointer, &llamafile_source(config).to_string())?;
+    }
+    info!("Verifying cached llamafile against its stored lock file");
+    let size = std::fs::metadata(&file_path)?.len();
+    let problem = match verify_model(&file_path, size)?.1 {
+        Status::Verified => return Ok(()),
+        Status::Incomplete => "is incomplete",
+        Status::Mismatch => "does not match the hash in its lock file",
+        _ => "cannot be checked against its lock file",
+    };
+    Err(AppError::OfflineError(format!(
+        "Cached llamafile {} {} and cannot be downloaded again offline",
+        file_path.display(),
+        problem
+    )))
+}
 
-    println!("{}", style("            --------------------------------------------------------------------------------").white().dim());
+/// Moves the completion marker that earlier versions appended to the model
+/// into its manifest, so the cached llamafile is unmodified again.
+fn migrate_legacy_cache(file_path: &Path, pointer: &LfsPointer, url: &str) -> Result<(), AppError> {
+    let Some(downloaded_at) = strip_legacy_trailer(file_path, pointer.size)? else {
+        return Ok(());
+    };
+    let manifest = manifest_path(file_path);
+    if !manifest.exists() {
+        Manifest {
+            url: url.to_string(),
+            sha256: pointer.sha256.clone(),
+            size: pointer.size,
+            downloaded_at,
+            llamafile_version: LLAMAFILE_VERSION.to_string(),
+        }
+        .write(&manifest)?;
+    }
+    Ok(())
+}
 
-    println!(
-        "{}",
-        style(
-            "
-            We won't show this notice again. Unless you delete your cache.
+/// Whether a request failed because the host could not be reached at all.
+fn is_unreachable(error: &AppError) -> bool {
+    matches!(error, AppError::HttpError(e) if e.is_connect() || e.is_timeout())
+}
 
-            Which, by the way, lives here: ~/.cache/fwj/
+pub async fn download_flow_judge_llamafile(config: &Config) -> Result<(), AppError> {
+    show_welcome(config)?;
 
-            Press any key to continue.
-    "
-        )
-        .white()
-        .dim()
-    );
+    info!("\n{}", style("Checking Flow-Judge-v0.1 llamafile"));
 
-    println!("{}", style("            ❤\n").red());
+    let file_path = PathBuf::from(&config.cache_dir).join(LLAMAFILE_NAME);
 
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input)?;
+    // Parallel runs wait here until the first one has verified or downloaded the model
+    let _lock = FileLock::exclusive_async(&file_path).await?;
 
-    info!("\n{}", style("Checking Flow-Judge-v0.1 llamafile"));
+    if is_offline(config) {
+        info!("Offline mode: using the cached llamafile");
+        return use_cached_llamafile(config);
+    }
 
-    let file_path = PathBuf::from(&config.cache_dir).join("flow-judge.llamafile");
-    let lock_file_path = PathBuf::from(&config.cache_dir).join("flow-judge.llamafile.lock");
+    let lock_file_path = lock_path(&file_path);
 
     // Create the .cache directory if it doesn't exist
     tokio::fs::create_dir_all(&config.cache_dir).await?;
 
-    // Fetch and save the lock file
-    fetch_and_save_lock_file(&config.llamafile_url, &lock_file_path).await?;
+    let client = http_client(config)?;
+    let llamafile_source = llamafile_source(config);
+
+    // Fetch and save the lock file, falling back to the cache when offline
+    let lock_source = lock_source(config);
+    if let Err(e) = fetch_and_save_lock_file(&client, &lock_source, &lock_file_path).await {
+        if !is_unreachable(&e) {
+            return Err(e);
+        }
+        warn!("Network unreachable ({}), continuing offline", e);
+        return use_cached_llamafile(config);
+    }
+
+    let pointer = LfsPointer::read(&lock_file_path).ok_or_else(|| {
+        AppError::ParseError(format!(
+            "Lock file {} is not a Git LFS pointer with a hash and size",
+            lock_file_path.display()
+        ))
+    })?;
 
     // Check if file exists and verify
-    if let Ok(_metadata) = tokio::fs::metadata(&file_path).await {
+    if let Ok(metadata) = tokio::fs::metadata(&file_path).await {
         info!("Existing llamafile found. Verifying...");
 
-        if verify_file(&file_path, &lock_file_path).await? {
-            info!(
-                "{}",
-                style("Verification passed. Using existing llamafile.")
-            );
-            return Ok(());
-        } else {
-            info!("Verification failed. Re-downloading llamafile.");
+        migrate_legacy_cache(&file_path, &pointer, &llamafile_source.to_string())?;
+        let metadata = tokio::fs::metadata(&file_path).await.unwrap_or(metadata);
+
+        match verify_model(&file_path, metadata.len())?.1 {
+            Status::Verified => {
+                info!(
+                    "{}",
+                    style("Verification passed. Using existing llamafile.")
+                );
+                if let Some(manifest) = Manifest::read(&manifest_path(&file_path)) {
+                    info!(
+                        "Downloaded from {} at {} (llamafile {})",
+                        manifest.url, manifest.downloaded_at, manifest.llamafile_version
+                    );
+                }
+                return Ok(());
+            }
+            Status::Incomplete => info!("Existing llamafile is incomplete. Re-downloading."),
+            _ => info!("Hash mismatch. Re-downloading llamafile."),
         }
     }
 
@@ -111,20 +204,16 @@ pub async fn download_flow_judge_llamafile(config: &Config) -> Result<(), AppErr
     );
     println!("\n{}", style("File details:").yellow());
     println!("  Name: {}", style("flow-judge.llamafile").green());
-    println!("  Size: {}", style("2.4 GB").green());
-    println!("  URL: {}", style(&config.llamafile_url).green());
+    println!("  Size: {}", style(HumanBytes(pointer.size)).green());
+    println!("  URL: {}", style(&llamafile_source).green());
     println!("  Date added to hub: {}", style("25.09.2024").green());
+    println!("  SHA256: {}", style(&pointer.sha256).green());
     println!(
-        "  SHA256: {}",
-        style("4845b598e88dbae320d2773edc15b52e054a53dd3a64b069121c33c3806c2dec").green()
+        "  Llamafile version: {}\n",
+        style(LLAMAFILE_VERSION).green()
     );
-    println!("  Llamafile version: {}\n", style("v0.8.13").green());
 
-    // Clone the URL before moving it into the async block
-    let llamafile_url = config.llamafile_url.clone();
-    let client = Client::new();
-    let total_size: u64 = 2_404_988_741;
-    let pb = ProgressBar::new(total_size);
+    let pb = ProgressBar::new(pointer.size);
     pb.set_style(ProgressStyle::default_bar()
         .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({percent}%) {eta}")
         .unwrap()
@@ -143,16 +232,22 @@ pub async fn download_flow_judge_llamafile(config: &Config) -> Result<(), AppErr
     // Ensure the progress bar starts at 0
     pb.set_position(0);
 
-    // Start the download
-    let mut response = client.get(&llamafile_url).send().await?;
-    let mut file = tokio::fs::File::create(file_path.to_str().unwrap()).await?;
-    let mut downloaded: u64 = 0;
-
-    while let Some(chunk) = response.chunk().await? {
-        file.write_all(&chunk).await?;
-        downloaded += chunk.len() as u64;
-        pb.set_position(downloaded);
-    }
+    // Start the download, or copy from a local mirror
+    let sha256 = match &llamafile_source {
+        Source::Http(url) => {
+            download_resumable(
+                &client,
+                url,
+                &file_path,
+                &pointer,
+                &pb,
+                DOWNLOAD_RETRIES,
+                INITIAL_BACKOFF,
+            )
+            .await?
+        }
+        Source::File(path) => copy_verified(path, &file_path, &pointer, &pb).await?,
+    };
 
     pb.finish_with_message("Download completed");
 
@@ -164,7 +259,15 @@ pub async fn download_flow_judge_llamafile(config: &Config) -> Result<(), AppErr
     tokio::fs::set_permissions(file_path.to_str().unwrap(), perms).await?;
 
     // After successful download
-    set_download_complete_flag(file_path.to_str().unwrap()).await?;
+    Manifest {
+        url: llamafile_source.to_string(),
+        sha256: sha256.clone(),
+        size: pointer.size,
+        downloaded_at: chrono::Utc::now().to_rfc3339(),
+        llamafile_version: LLAMAFILE_VERSION.to_string(),
+    }
+    .write(&manifest_path(&file_path))?;
+    record_sha256(&file_path, &sha256);
 
     println!(
         "\n\n{}",
@@ -179,100 +282,338 @@ pub async fn download_flow_judge_llamafile(config: &Config) -> Result<(), AppErr
     Ok(())
 }
 
-async fn fetch_and_save_lock_file(_url: &str, lock_file_path: &PathBuf) -> Result<(), AppError> {
-    let client = Client::new();
-    let response = client.get(LLAMAFILE_LOCK_URL).send().await?.text().await?;
+/// The partial download of a file, kept until it is complete and verified.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
 
-    // Ensure the directory exists
-    if let Some(parent) = std::path::Path::new(lock_file_path).parent() {
-        fs::create_dir_all(parent).await?;
+/// Whether a failed download attempt is worth retrying.
+fn is_transient(error: &AppError) -> bool {
+    match error {
+        AppError::HttpError(e) => {
+            e.is_timeout()
+                || e.is_connect()
+                || e.is_request()
+                || e.is_body()
+                // A connection dropped mid-body surfaces as a decode error
+                || e.is_decode()
+                || e.status().is_some_and(|status| {
+                    status.is_server_error()
+                        || status == StatusCode::TOO_MANY_REQUESTS
+                        || status == StatusCode::REQUEST_TIMEOUT
+                })
+        }
+        AppError::IoError(e) => e.kind() == std::io::ErrorKind::UnexpectedEof,
+        _ => false,
     }
+}
 
-    let mut file = File::create(lock_file_path).await?;
-    file.write_all(response.as_bytes()).await?;
+/// Size of the whole file a response belongs to, from `Content-Range` for
+/// a resumed download and `Content-Length` otherwise.
+fn total_size(response: &reqwest::Response, offset: u64) -> Option<u64> {
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return response.content_length();
+    }
+    response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.rsplit('/').next())
+        .and_then(|total| total.parse().ok())
+        .or_else(|| Some(offset + response.content_length()?))
+}
 
-    Ok(())
+/// Downloads `url` into the part file of `path`, resuming where an earlier
+/// attempt or run stopped and retrying transient failures with exponential
+/// backoff. The part file is renamed to `path` only once its size and
+/// SHA-256 match `expected`; the verified hash is returned.
+pub async fn download_resumable(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    expected: &LfsPointer,
+    progress: &ProgressBar,
+    retries: u32,
+    initial_backoff: Duration,
+) -> Result<String, AppError> {
+    let part = part_path(path);
+    let mut hasher = Sha256::new();
+    let mut downloaded = 0;
+
+    // Hash what was downloaded before, so it is not fetched again
+    if let Ok(metadata) = tokio::fs::metadata(&part).await {
+        if metadata.len() <= expected.size {
+            let mut file = File::open(&part).await?;
+            let mut buffer = vec![0; 1024 * 1024];
+            loop {
+                let read = file.read(&mut buffer).await?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+                downloaded += read as u64;
+            }
+            info!("Resuming download after {}", HumanBytes(downloaded));
+        }
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&part)
+        .await?;
+    let mut backoff = initial_backoff;
+    let mut attempt = 0;
+    while downloaded < expected.size {
+        let result = fetch_range(
+            client,
+            url,
+            &mut file,
+            &mut hasher,
+            &mut downloaded,
+            progress,
+        )
+        .await;
+        // A response that ends early is resumed like a dropped connection,
+        // whether or not the server announced the full size
+        let result = result.and_then(|()| {
+            if downloaded < expected.size {
+                Err(AppError::IoError(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed before the download completed",
+                )))
+            } else {
+                Ok(())
+            }
+        });
+        match result {
+            Ok(()) => break,
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                warn!(
+                    "Download interrupted after {} ({}), retrying in {:?} ({}/{})",
+                    HumanBytes(downloaded),
+                    e,
+                    backoff,
+                    attempt,
+                    retries
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    file.sync_all().await?;
+    drop(file);
+
+    let sha256 = format!("{:x}", hasher.finalize());
+    put_in_place(&part, path, expected, downloaded, sha256).await
+}
+
+/// Copies a llamafile from a local mirror through a part file, verified
+/// like a download.
+async fn copy_verified(
+    source: &Path,
+    path: &Path,
+    expected: &LfsPointer,
+    progress: &ProgressBar,
+) -> Result<String, AppError> {
+    let part = part_path(path);
+    let size = tokio::fs::copy(source, &part).await.map_err(|e| {
+        AppError::FileReadError(format!("Failed to copy '{}': {}", source.display(), e))
+    })?;
+    progress.set_length(size);
+    progress.set_position(0);
+    let sha256 = sha256_file(&part, None, Some(progress))?;
+    put_in_place(&part, path, expected, size, sha256).await
+}
+
+/// Renames a complete part file to `path` if it matches the lock file, and
+/// removes it otherwise.
+async fn put_in_place(
+    part: &Path,
+    path: &Path,
+    expected: &LfsPointer,
+    size: u64,
+    sha256: String,
+) -> Result<String, AppError> {
+    if size != expected.size || sha256 != expected.sha256 {
+        tokio::fs::remove_file(part).await?;
+        return Err(AppError::DownloadError(format!(
+            "Downloaded file does not match its lock file: expected {} bytes with SHA-256 {}, got {} bytes with SHA-256 {}",
+            expected.size, expected.sha256, size, sha256
+        )));
+    }
+    tokio::fs::rename(part, path).await?;
+    Ok(sha256)
 }
 
-async fn set_download_complete_flag(file_path: &str) -> Result<(), AppError> {
-    let mut file = OpenOptions::new().write(true).open(file_path).await?;
+/// One download attempt, appending to `file` from `downloaded` bytes on.
+async fn fetch_range(
+    client: &Client,
+    url: &str,
+    file: &mut File,
+    hasher: &mut Sha256,
+    downloaded: &mut u64,
+    progress: &ProgressBar,
+) -> Result<(), AppError> {
+    let mut request = client.get(url);
+    if *downloaded > 0 {
+        request = request.header(RANGE, format!("bytes={}-", downloaded));
+    }
+    let mut response = request.send().await?;
+
+    // The server cannot resume from here, so start over
+    let cannot_resume = response.status() == StatusCode::RANGE_NOT_SATISFIABLE
+        || (*downloaded > 0 && response.status() == StatusCode::OK);
+    if cannot_resume {
+        info!("Server cannot resume the download, starting over");
+        *hasher = Sha256::new();
+        *downloaded = 0;
+        if response.status() != StatusCode::OK {
+            response = client.get(url).send().await?;
+        }
+    }
+    let mut response = response.error_for_status()?;
 
-    let metadata = json!({
-        "download_complete": true,
-        "timestamp": chrono::Utc::now().to_rfc3339()
-    });
+    // Drop whatever a failed write left behind the verified prefix
+    file.set_len(*downloaded).await?;
+    file.seek(std::io::SeekFrom::Start(*downloaded)).await?;
 
-    // Write metadata to the end of the file
-    file.seek(std::io::SeekFrom::End(0)).await?;
-    file.write_all(serde_json::to_string(&metadata)?.as_bytes())
-        .await?;
+    let total = total_size(&response, *downloaded);
+    if let Some(total) = total {
+        progress.set_length(total);
+    }
+    progress.set_position(*downloaded);
 
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+        hasher.update(&chunk);
+        *downloaded += chunk.len() as u64;
+        progress.set_position(*downloaded);
+    }
+    file.flush().await?;
     Ok(())
 }
 
-async fn verify_file(file_path: &PathBuf, lock_file_path: &PathBuf) -> Result<bool, AppError> {
-    info!("Starting verification process");
+/// Shows the welcome notice and waits for Enter, once per cache directory.
+///
+/// Skipped in non-interactive mode and whenever stdin is not a terminal, so
+/// CI jobs and cron runs never block on it.
+fn show_welcome(config: &Config) -> Result<(), AppError> {
+    let marker = PathBuf::from(&config.cache_dir).join(WELCOME_MARKER);
+    if marker.exists() {
+        return Ok(());
+    }
+    if config.noninteractive || !std::io::stdin().is_terminal() {
+        info!("Skipping the welcome notice in non-interactive mode");
+        return Ok(());
+    }
+
+    println!(
+        "{}",
+        style(
+            "
 
-    let lock_content = tokio::fs::read_to_string(lock_file_path)
-        .await
-        .map_err(|e| {
-            info!("Failed to read lock file: {}. Will download.", e);
-            AppError::IoError(e)
-        })?;
+             F   L   O   W   A   I
 
-    if lock_content.trim().is_empty() {
-        info!("Lock file is empty. Will download.");
-        return Ok(false);
-    }
+            ⣀⣀⣀⣀⣀⣀⣀⣀⣀⣀⣀⡀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀
+    ⠀⠀⠀⠀⠀⠀⠀⢸⣿⣿⣿⣿⣿⣿⣿⣿⣿⣿⣿⡿⠀⠀⢀⣀⡀⠀⠀⠀⠀⠀⠀⠀
+    ⠀⠀⠀⠀⠀⠀⠀⢸⣿⣿⣿⣿⣿⣿⣿⣿⣿⡿⠋⠀⢀⣴⣿⣿⡇⠀⠀⠀⠀⠀⠀⠀
+    ⠀⠀⠀⠀⠀⠀⠀⠈⠛⠛⠛⠛⠛⠛⠛⠛⠋⠀⢀⣴⣿⣿⣿⣿⡇⠀⠀⠀⠀⠀⠀⠀
+    ⠀⠀⠀⠀⠀⠀⠀⢀⣤⣤⣤⣤⡄⠀⠀⠀⢀⣴⣿⣿⣿⣿⣿⣿⡇⠀⠀⠀⠀⠀⠀⠀
+    ⠀⠀⠀⠀⠀⠀⠀⢸⣿⣿⣿⡿⠃⠀⢀⣴⣿⣿⣿⣿⣿⣿⣿⣿⡇⠀⠀⠀⠀⠀⠀⠀
+    ⠀⠀⠀⠀⠀⠀⠀⠸⣿⡿⠋⠀⠀⢰⣿⣿⣿⣿⣿⣿⣿⣿⣿⣿⡇⠀⠀⠀⠀⠀⠀⠀
+    ⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠉⠉⠉⠉⠉⠉⠉⠉⠉⠉⠀⠀⠀⠀⠀⠀⠀⠀
+    "
+        )
+        .white()
+    );
+
+    println!("{}", style("            --------------------------------------------------------------------------------").white().dim());
 
-    info!("Lock file content: {}", lock_content);
+    println!("{}", style("
+            Welcome friend.
+
+            This is a quick-start for the Flow-Judge-v0.1 model.
+
+            This tool can evaluate 'input' and 'output' pairs from csv and json files.
+
+            The program will add columns 'score' and 'feedback' to the given file, editing it in-place.
+
+            Before you begin you might want to read the instructions from the model card:
+
+            https://huggingface.co/flowaicom/Flow-Judge-v0.1#prompt-format
+    ").white());
+
+    println!("{}", style("            --------------------------------------------------------------------------------").white().dim());
+
+    println!(
+        "{}",
+        style(format!(
+            "
+            We won't show this notice again. Unless you delete your cache.
 
-    let expected_hash = lock_content
-        .lines()
-        .find(|line| line.starts_with("oid sha256:"))
-        .and_then(|line| line.split(':').nth(1))
-        .ok_or_else(|| AppError::ParseError("Failed to parse hash from lock file".to_string()))?;
+            Which, by the way, lives here: {}
 
-    info!("Expected hash from lock file: {}", expected_hash);
+            Press Enter to continue.
+    ",
+            config.cache_dir
+        ))
+        .white()
+        .dim()
+    );
 
-    if expected_hash != expected_hash {
-        info!("Hash mismatch between lock file and URL");
-        return Ok(false);
+    println!("{}", style("            ❤\n").red());
+
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+
+    std::fs::create_dir_all(&config.cache_dir)?;
+    std::fs::write(marker, chrono::Utc::now().to_rfc3339())?;
+    Ok(())
+}
+
+/// Fetches the lock file and stores it, unless it is not a Git LFS pointer,
+/// so a broken mirror never replaces the lock a cached model was checked with.
+async fn fetch_and_save_lock_file(
+    client: &Client,
+    source: &Source,
+    lock_file_path: &PathBuf,
+) -> Result<(), AppError> {
+    let response = read_text(client, source).await?;
+    if LfsPointer::parse(&response).is_none() {
+        return Err(AppError::ParseError(format!(
+            "Lock file from {} is not a Git LFS pointer with a hash and size",
+            source
+        )));
     }
 
-    let mut file = File::open(file_path).await?;
-    let metadata = file.metadata().await?;
-    let file_size = metadata.len();
-
-    // Check for download_complete flag
-    if file_size > 200 {
-        file.seek(tokio::io::SeekFrom::End(-200)).await?;
-        let mut buffer = vec![0; 200];
-        file.read_exact(&mut buffer).await?;
-
-        // Try to find valid JSON at the end of the buffer
-        let metadata_str = String::from_utf8_lossy(&buffer);
-        if let Some(json_start) = metadata_str.rfind('{') {
-            let clean_metadata = &metadata_str[json_start..];
-            info!("Cleaned metadata buffer: {}", clean_metadata);
-
-            let download_complete = serde_json::from_str::<HashMap<String, Value>>(clean_metadata)
-                .map(|metadata| metadata.get("download_complete") == Some(&json!(true)))
-                .unwrap_or(false);
-
-            if download_complete {
-                info!("Download complete flag found and hash matches");
-                return Ok(true);
-            }
-        }
+    // Ensure the directory exists
+    if let Some(parent) = std::path::Path::new(lock_file_path).parent() {
+        fs::create_dir_all(parent).await?;
     }
 
-    info!("Download complete flag not found or false");
-    Ok(false)
+    let mut file = File::create(lock_file_path).await?;
+    file.write_all(response.as_bytes()).await?;
+    file.flush().await?;
+
+    Ok(())
 }
 
-pub async fn download_file(url: &str, file_path: &str) -> Result<(), AppError> {
-    println!("Downloading file from: {}", style(url).yellow());
+pub async fn download_file(
+    client: &Client,
+    source: &Source,
+    file_path: &str,
+) -> Result<(), AppError> {
+    println!("Downloading file from: {}", style(source).yellow());
+
+    // Another process may be downloading the same file
+    let _lock = FileLock::exclusive_async(Path::new(file_path)).await?;
 
     // Check if the file already exists
     if tokio::fs::metadata(file_path).await.is_ok() {
@@ -283,17 +624,23 @@ pub async fn download_file(url: &str, file_path: &str) -> Result<(), AppError> {
         return Ok(());
     }
 
-    let client = Client::new();
-    let response = client.get(url).send().await?;
+    let content = match source {
+        Source::Http(url) => {
+            let response = client.get(url).send().await?;
 
-    if !response.status().is_success() {
-        return Err(AppError::DownloadError(format!(
-            "Failed to download file: HTTP {}",
-            response.status()
-        )));
-    }
+            if !response.status().is_success() {
+                return Err(AppError::DownloadError(format!(
+                    "Failed to download file: HTTP {}",
+                    response.status()
+                )));
+            }
 
-    let content = response.bytes().await?;
+            response.bytes().await?.to_vec()
+        }
+        Source::File(path) => tokio::fs::read(path).await.map_err(|e| {
+            AppError::FileReadError(format!("Failed to read '{}': {}", path.display(), e))
+        })?,
+    };
 
     // Ensure the directory exists
     if let Some(parent) = std::path::Path::new(file_path).parent() {
@@ -308,6 +655,7 @@ pub async fn download_file(url: &str, file_path: &str) -> Result<(), AppError> {
         .await?;
 
     file.write_all(&content).await?;
+    file.flush().await?;
 
     println!("File downloaded and saved to: {}", style(file_path).green());
     Ok(())
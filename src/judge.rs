@@ -0,0 +1,96 @@
+use crate::download::download_flow_judge_llamafile;
+use crate::models::{AppError, Config, RunParams};
+use crate::{
+    execute_llamafile_with_timeout, llamafile_path, load_rubric, parse_feedback, parse_score,
+    populate_template, save_last_result,
+};
+use console::style;
+use log::info;
+use minijinja::context;
+use std::io::Read;
+
+/// Resolves the input and output of `fwj judge` from the inline values or
+/// files, where a file of "-" is read from stdin.
+pub fn read_pair(
+    input: Option<&str>,
+    input_file: Option<&str>,
+    output: Option<&str>,
+    output_file: Option<&str>,
+) -> Result<(String, String), AppError> {
+    if input_file == Some("-") && output_file == Some("-") {
+        return Err(AppError::ConfigError(
+            "Only one of --input-file and --output-file can be read from stdin".to_string(),
+        ));
+    }
+    Ok((
+        read_text(input, input_file)?,
+        read_text(output, output_file)?,
+    ))
+}
+
+fn read_text(text: Option<&str>, file: Option<&str>) -> Result<String, AppError> {
+    match (text, file) {
+        (Some(text), _) => Ok(text.to_string()),
+        (None, Some("-")) => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            Ok(text)
+        }
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .map_err(|e| AppError::FileReadError(format!("Failed to read '{}': {}", path, e))),
+        (None, None) => Ok(String::new()),
+    }
+}
+
+/// Runs `fwj judge`: renders the rubric for one input/output pair, runs the
+/// judge and prints the parsed feedback and score.
+///
+/// Fails when no score can be parsed from the judge output, so scripts can
+/// rely on the exit code.
+pub async fn run(
+    config: &Config,
+    params: &RunParams,
+    rubric_template: &str,
+    input: &str,
+    output: &str,
+) -> Result<(), AppError> {
+    let rubric = load_rubric(rubric_template).await?;
+    let prompt = populate_template(&rubric, &context! { input => input, output => output })?;
+
+    if params.model.is_none() {
+        info!("Downloading Flow Judge llamafile");
+        download_flow_judge_llamafile(config).await?;
+    }
+
+    println!("{}", style("Judging...").yellow().bold());
+    let llamafile_run =
+        execute_llamafile_with_timeout(&prompt, &llamafile_path(config, params), params).await?;
+    let llamafile_output = llamafile_run.output;
+    save_last_result(&llamafile_output, &config.cache_dir)?;
+
+    let feedback = parse_feedback(&llamafile_output);
+    let score = parse_score(&llamafile_output);
+
+    match &feedback {
+        Some(feedback) => println!("\n{}\n{}", style("Feedback:").bold().underlined(), feedback),
+        None => println!(
+            "\n{}\n{}",
+            style("Judge output:").bold().underlined(),
+            llamafile_output.trim()
+        ),
+    }
+
+    match score {
+        Some(score) => {
+            println!(
+                "\n{} {}",
+                style("Score:").bold(),
+                style(score).green().bold()
+            );
+            Ok(())
+        }
+        None => Err(AppError::ParseError(
+            "No score found in the judge output".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,97 @@
+use crate::models::AppError;
+use log::info;
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// Suffix of the hidden files locks are taken on, next to the file they guard.
+pub const GUARD_SUFFIX: &str = ".lck";
+
+/// An advisory lock shared by all `fwj` processes on a machine, released
+/// when dropped.
+///
+/// The lock is taken on a separate guard file, so the file it protects can
+/// be replaced or removed while the lock is held. Locks are per open file:
+/// a process that already holds one must not take it again.
+#[derive(Debug)]
+pub struct FileLock {
+    _lock: Flock<File>,
+}
+
+impl FileLock {
+    /// Takes the lock for writing `path`, waiting while another process holds it.
+    pub fn exclusive(path: &Path) -> Result<Self, AppError> {
+        Self::acquire(
+            path,
+            FlockArg::LockExclusiveNonblock,
+            FlockArg::LockExclusive,
+        )
+    }
+
+    /// Takes a lock for reading `path`, waiting while another process writes it.
+    pub fn shared(path: &Path) -> Result<Self, AppError> {
+        Self::acquire(path, FlockArg::LockSharedNonblock, FlockArg::LockShared)
+    }
+
+    /// Takes the lock for writing `path` unless another process holds it.
+    pub fn try_exclusive(path: &Path) -> Result<Option<Self>, AppError> {
+        Self::lock(path, FlockArg::LockExclusiveNonblock)
+    }
+
+    /// Takes a lock for reading `path` unless another process is writing it.
+    pub fn try_shared(path: &Path) -> Result<Option<Self>, AppError> {
+        Self::lock(path, FlockArg::LockSharedNonblock)
+    }
+
+    /// Like [`FileLock::exclusive`], waiting on a blocking thread so the
+    /// runtime keeps going.
+    pub async fn exclusive_async(path: &Path) -> Result<Self, AppError> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || Self::exclusive(&path)).await?
+    }
+
+    fn acquire(path: &Path, now: FlockArg, wait: FlockArg) -> Result<Self, AppError> {
+        if let Some(lock) = Self::lock(path, now)? {
+            return Ok(lock);
+        }
+        info!(
+            "Waiting for another fwj process to finish with {}",
+            path.display()
+        );
+        Self::lock(path, wait)?
+            .ok_or_else(|| AppError::CustomError(format!("Failed to lock '{}'", path.display())))
+    }
+
+    fn lock(path: &Path, arg: FlockArg) -> Result<Option<Self>, AppError> {
+        let guard = guard_path(path);
+        if let Some(parent) = guard.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&guard)
+            .map_err(|e| {
+                AppError::FileWriteError(format!(
+                    "Failed to open lock file '{}': {}",
+                    guard.display(),
+                    e
+                ))
+            })?;
+        match Flock::lock(file, arg) {
+            Ok(lock) => Ok(Some(Self { _lock: lock })),
+            Err((_, Errno::EWOULDBLOCK)) => Ok(None),
+            Err((_, errno)) => Err(AppError::IoError(errno.into())),
+        }
+    }
+}
+
+/// The hidden file next to `path` the lock guarding it is taken on.
+pub fn guard_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(GUARD_SUFFIX);
+    path.with_file_name(name)
+}
@@ -1,30 +1,50 @@
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::module_name_repetitions)]
 
+mod bench;
+mod bundle;
+mod cache;
 mod cli;
+mod config;
+mod diff;
 mod download;
+mod judge;
+mod lock;
+mod merge;
+mod meta_eval;
 mod models;
+mod report;
+mod result_cache;
+mod selection;
+mod shutdown;
+mod source;
+mod stats;
+mod store;
 #[cfg(test)]
 mod tests;
 
-use models::{AppError, Config, IoItem, TaskConfig};
-use models::{DATA_URL, RUBRIC_URL};
-use models::{FILE_LOCKS, MAX_RETRIES, RUBRICS_DIR, SCORE_REGEX};
+use models::{AppError, BackupMode, Config, IoItem, RunParams, SelectionConfig, TaskConfig};
+use models::{EVAL_TIMING_REGEX, FEEDBACK_REGEX, FILE_LOCKS, MAX_RETRIES, RUBRICS_DIR, SCORE_REGEX};
 use std::path::Path;
 
 use crate::download::{download_file, download_flow_judge_llamafile};
+use crate::lock::FileLock;
+use crate::result_cache::ResultCache;
+use crate::shutdown::Shutdown;
+use crate::source::FetchSources;
+use crate::store::{sha256_hex, JudgmentRecord, ResultsStore, RunRecord};
 
-use crate::cli::Args;
 use clap::CommandFactory;
 use clap_complete::generate;
 use console::style;
 use csv::{ReaderBuilder, WriterBuilder};
 use env_logger::Env;
+use futures::future;
 use futures::stream::{self, StreamExt};
+use indexmap::IndexSet;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use log::{debug, error, info, warn};
 use minijinja::{context, Environment};
-use mistralrs::{IsqType, GgufModelBuilder, TextMessageRole, TextMessages, TextModelBuilder, PagedAttentionMetaBuilder, MemoryGpuConfig};
 use regex::Regex;
 use serde_json::{self, Value};
 use std::fs::File;
@@ -33,39 +53,13 @@ use std::io::{BufReader, BufWriter, Write};
 use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
 use std::process::Stdio;
-use std::str::from_utf8;
 use std::sync::Arc;
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::fs;
 use tokio::sync::Mutex;
 use anyhow::Result;
 
-impl Config {
-    pub fn from_file(path: &str) -> Result<Self, AppError> {
-        let config_str = std::fs::read_to_string(path).map_err(|e| {
-            AppError::ConfigError(format!("Failed to read config file {}: {}", path, e))
-        })?;
-
-        let config: Config = if path.ends_with(".yaml") || path.ends_with(".yml") {
-            serde_yml::from_str(&config_str).map_err(|e| {
-                AppError::ConfigError(format!("Failed to parse YAML config file {}: {}", path, e))
-            })?
-        } else if path.ends_with(".json") {
-            serde_json::from_str(&config_str).map_err(|e| {
-                AppError::ConfigError(format!("Failed to parse JSON config file {}: {}", path, e))
-            })?
-        } else {
-            return Err(AppError::ConfigError(format!(
-                "Unsupported config file format: {}",
-                path
-            )));
-        };
-
-        Ok(config)
-    }
-}
-
 fn display_last_result(result: &str) {
     println!(
         "\n{}",
@@ -80,37 +74,21 @@ fn display_last_result(result: &str) {
 }
 
 #[tokio::main]
-async fn main() -> Result<(), AppError> {
-    let model = GgufModelBuilder::new(
-        "flowaicom/Flow-Judge-v0.1-GGUF",
-        vec!["Flow-Judge-v0.1-Q4_K_M.gguf"],
-    )
-    .with_tok_model_id("flowaicom/Flow-Judge-v0.1")
-    .with_logging()
-    .build()
-    .await?;
-
-    let messages = TextMessages::new()
-        .add_message(
-            TextMessageRole::System,
-            "You are an AI agent with a specialty in programming.",
-        )
-        .add_message(
-            TextMessageRole::User,
-            "Hello! How are you? Please write generic binary search function in Rust.",
-        );
-
-    let response = model.send_chat_request(messages).await?;
-
-    println!("{}", response.choices[0].message.content.as_ref().unwrap());
-    dbg!(
-        response.usage.avg_prompt_tok_per_sec,
-        response.usage.avg_compl_tok_per_sec
-    );
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("Error: {:?}", e);
+        let code = match e {
+            AppError::InterruptedError(_) => shutdown::INTERRUPTED_EXIT_CODE,
+            _ => 1,
+        };
+        std::process::exit(code);
+    }
+}
 
+async fn run() -> Result<(), AppError> {
     let args = cli::parse_args();
 
-    if let Some(cli::Commands::GenAutoCompletions { shell, output }) = args.command {
+    if let Some(cli::Commands::GenAutoCompletions { shell, output }) = &args.command {
         let mut cmd = cli::Args::command();
         let mut buf = Vec::new();
 
@@ -133,7 +111,7 @@ async fn main() -> Result<(), AppError> {
         }
 
         if let Some(path) = output {
-            File::create(&path)
+            File::create(path)
                 .map_err(|e| {
                     AppError::FileWriteError(format!("Failed to create file '{}': {}", path, e))
                 })?
@@ -169,79 +147,308 @@ async fn main() -> Result<(), AppError> {
 
     info!("Starting application");
 
-    let mut config = Config {
-        tasks: vec![],
-        llamafile_url: models::default_llamafile_url(),
-        cache_dir: models::default_cache_dir(),
-        rubrics_dir: models::default_rubrics_dir(),
-        data_dir: models::default_data_dir(),
-    };
+    if let Some(cli::Commands::Merge {
+        files,
+        output,
+        id_field,
+        on_conflict,
+        require_complete,
+    }) = &args.command
+    {
+        return merge::run(files, output, id_field, *on_conflict, *require_complete);
+    }
 
-    // Check if config file exists
-    if std::path::Path::new(&args.config).exists() {
-        info!("Loading configuration from {}", args.config);
-        config = Config::from_file(&args.config)?;
-    } else {
-        info!("No config file found at {}", args.config);
+    if let Some(cli::Commands::Report {
+        file,
+        by,
+        format,
+        resamples,
+        confidence,
+        seed,
+    }) = &args.command
+    {
+        let options = report::ReportOptions {
+            by: by.clone(),
+            resamples: *resamples,
+            confidence: *confidence,
+            seed: *seed,
+        };
+        return report::run(file, *format, &options);
     }
 
-    // Handle data file
-    let data_path = if args.data == "fetch" {
-        let path = format!("{}/subquery-data.json", config.data_dir);
-        if !Path::new(&path).exists() {
-            download_file(DATA_URL, &path).await?;
-        }
-        path
-    } else {
-        args.data.clone()
-    };
+    if let Some(cli::Commands::Diff {
+        old,
+        new,
+        id_field,
+        top,
+        all,
+    }) = &args.command
+    {
+        return diff::run(old, new, id_field, *top, *all);
+    }
 
-    // Handle rubric file
-    let rubric_template = if args.rubric == "fetch" {
-        let path = Path::new(&config.rubrics_dir).join("subquery-decomp.jinja");
-        if !path.exists() {
-            download_file(RUBRIC_URL, path.to_str().unwrap()).await?;
+    if let Some(cli::Commands::MetaEval {
+        file,
+        human_column,
+        judge_column,
+        top,
+        format,
+    }) = &args.command
+    {
+        return meta_eval::run(file, human_column, judge_column, *top, *format);
+    }
+
+    let resolved_config = config::load(&args)?;
+
+    if let Some(cli::Commands::Config {
+        action: cli::ConfigCommand::Show { resolved },
+    }) = &args.command
+    {
+        config::show(&resolved_config, *resolved);
+        return Ok(());
+    }
+
+    if let Some(cli::Commands::Cache { action }) = &args.command {
+        let config = &resolved_config.config;
+        return match action {
+            cli::CacheCommand::List => cache::list(config),
+            cli::CacheCommand::Verify => cache::verify(config),
+            cli::CacheCommand::Path => {
+                println!("{}", config.cache_dir);
+                Ok(())
+            }
+            cli::CacheCommand::Clean {
+                stale,
+                models,
+                run_data,
+                results,
+                all,
+                older_than,
+                dry_run,
+            } => {
+                let options = cache::CleanOptions {
+                    stale: *stale || !(*models || *run_data || *results || *all),
+                    models: *models,
+                    run_data: *run_data,
+                    results: *results,
+                    all: *all,
+                    older_than: *older_than,
+                    dry_run: *dry_run,
+                };
+                cache::clean(config, &options)
+            }
+        };
+    }
+
+    if let Some(cli::Commands::Bundle { action }) = &args.command {
+        let config = &resolved_config.config;
+        return match action {
+            cli::BundleCommand::Export { output } => {
+                bundle::export(config, &args.config, Path::new(output))
+            }
+            cli::BundleCommand::Import {
+                archive,
+                dest,
+                force,
+            } => bundle::import(config, Path::new(archive), Path::new(dest), *force),
+        };
+    }
+
+    let mut config = resolved_config.config;
+
+    let profile = config.active_profile()?.cloned().unwrap_or_default();
+    if let Some(name) = &config.profile {
+        info!("Using profile '{}'", name);
+    }
+
+    if let Some(cli::Commands::Judge {
+        rubric,
+        input,
+        input_file,
+        output,
+        output_file,
+    }) = &args.command
+    {
+        let params = args.run_config().or(&profile.run).or(&config.run).resolve();
+        let (input, output) = judge::read_pair(
+            input.as_deref(),
+            input_file.as_deref(),
+            output.as_deref(),
+            output_file.as_deref(),
+        )?;
+        return judge::run(&config, &params, rubric, &input, &output).await;
+    }
+
+    if let Some(cli::Commands::Bench {
+        rubric,
+        data,
+        items,
+        batch_sizes,
+        threads,
+    }) = &args.command
+    {
+        let params = args.run_config().or(&profile.run).or(&config.run).resolve();
+        let default_rubric = Path::new(&config.rubrics_dir)
+            .join("subquery-decomp.jinja")
+            .to_string_lossy()
+            .into_owned();
+        let default_data = format!("{}/subquery-data.json", config.data_dir);
+        let rubric = rubric.clone().unwrap_or(default_rubric);
+        let data = data.clone().unwrap_or(default_data);
+        for path in [&rubric, &data] {
+            if !Path::new(path).is_file() {
+                return Err(AppError::ConfigError(format!(
+                    "{} not found; pass --rubric and --data, or run fwj once to fetch the defaults",
+                    path
+                )));
+            }
         }
-        path.to_str().unwrap().to_string()
+        bench::run(
+            &config,
+            &params,
+            &rubric,
+            &data,
+            *items,
+            batch_sizes,
+            threads,
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // An explicit --data or --rubric replaces the tasks from the config file
+    if args.data.is_some() || args.rubric.is_some() || config.tasks.is_empty() {
+        config.tasks = vec![models::TaskConfig {
+            name: None,
+            data: vec![args.data.clone().unwrap_or_else(|| "fetch".to_string())],
+            rubric_template: args.rubric.clone().unwrap_or_else(|| "fetch".to_string()),
+            output: args.output.clone(),
+            run: models::RunConfig::default(),
+            selection: SelectionConfig::default(),
+        }];
     } else {
-        args.rubric.clone()
-    };
+        let mut selected = Vec::with_capacity(config.tasks.len());
+        for task_config in config.tasks.drain(..) {
+            if profile.selects(&task_config)? {
+                selected.push(task_config);
+            }
+        }
+        config.tasks = selected;
+        info!(
+            "Using {} task(s) from {}",
+            config.tasks.len(),
+            args.config
+        );
+    }
 
-    config.tasks = vec![models::TaskConfig {
-        data: data_path,
-        rubric_template,
-    }];
+    let offline = download::is_offline(&config);
+    let sources = FetchSources::new(&config)?;
+    let mut missing = Vec::new();
+    for task_config in &mut config.tasks {
+        missing.extend(
+            resolve_fetch_sources(
+                task_config,
+                &config.data_dir,
+                &config.rubrics_dir,
+                &sources,
+                offline,
+            )
+            .await?,
+        );
+    }
 
     // Ensure we have tasks to process
     if config.tasks.is_empty() {
         return Err(AppError::ConfigError(
-            "No tasks found in configuration or CLI arguments".to_string(),
+            "No tasks found in configuration or CLI arguments, or none selected by the profile"
+                .to_string(),
         ));
     }
 
-    // Download the llamafile and wait for it to complete
-    info!("Downloading Flow Judge llamafile");
-    download_flow_judge_llamafile(&config).await?;
-    info!("Download completed successfully");
+    // Command line options win over the profile, then the task, then the config file
+    let cli_run = args.run_config();
+    let task_params: Vec<RunParams> = config
+        .tasks
+        .iter()
+        .map(|task_config| {
+            cli_run
+                .or(&profile.run)
+                .or(&task_config.run)
+                .or(&config.run)
+                .resolve()
+        })
+        .collect();
+    let cli_selection = args.selection_config();
+    let task_selections: Vec<SelectionConfig> = config
+        .tasks
+        .iter()
+        .map(|task_config| {
+            cli_selection
+                .or(&profile.selection)
+                .or(&task_config.selection)
+                .or(&config.selection)
+        })
+        .collect();
+    let batch_size = args.batch_size.or(profile.batch_size).unwrap_or(1).max(1);
+
+    // Offline, report everything that is not cached before starting any work
+    let needs_model = task_params.iter().any(|params| params.model.is_none());
+    if offline {
+        if needs_model {
+            missing.extend(download::missing_llamafile_artifacts(&config));
+        }
+        download::require_cached(&missing)?;
+    }
+
+    // Download the llamafile and wait for it to complete, unless every task brings its own model
+    if needs_model {
+        info!("Downloading Flow Judge llamafile");
+        download_flow_judge_llamafile(&config).await?;
+        info!("Download completed successfully");
+    }
+
+    let mut results_store = config
+        .results_db
+        .as_deref()
+        .map(ResultsStore::open)
+        .transpose()?;
 
     let mut parsing_failures = 0;
+    let mut timeouts = 0;
     let mut last_result = String::new();
 
+    // From here on, Ctrl-C and SIGTERM stop the run after writing what is judged
+    let shutdown = Shutdown::new();
+    shutdown.listen()?;
+
     // Process tasks
     info!("Starting task processing");
-    for task_config in &config.tasks {
+    for ((task_config, params), selection) in
+        config.tasks.iter().zip(&task_params).zip(&task_selections)
+    {
         info!(
             "Processing task with rubric: {}",
             task_config.rubric_template
         );
-        match process_task(task_config, &config, args.batch_size, &args).await {
-            Ok((failures, result)) => {
+        let mut context = JudgeContext {
+            config: &config,
+            params,
+            selection,
+            results_store: results_store.as_mut(),
+            shutdown: &shutdown,
+        };
+        match process_task(task_config, batch_size, &mut context).await {
+            Ok((failures, timed_out, result)) => {
                 info!(
                     "Task with rubric '{}' processed successfully",
                     task_config.rubric_template
                 );
                 parsing_failures += failures;
+                timeouts += timed_out;
                 last_result = result;
+                if shutdown.is_stopping() {
+                    break;
+                }
             }
             Err(e) => {
                 error!(
@@ -262,15 +469,21 @@ async fn main() -> Result<(), AppError> {
         display_last_result(&last_result);
     }
 
+    if shutdown.is_stopping() {
+        return Err(AppError::InterruptedError(
+            "stopped by a signal after writing the completed judgments".to_string(),
+        ));
+    }
+
     // Display summary
-    if parsing_failures == 0 {
+    if parsing_failures == 0 && timeouts == 0 {
         println!("\n{}", style("All items processed successfully.").yellow());
     } else if parsing_failures == 1 {
         println!(
             "\n{}",
             style(format!("Processing completed with 1 parsing failure.")).yellow()
         );
-    } else {
+    } else if parsing_failures > 1 {
         println!(
             "\n{}",
             style(format!(
@@ -280,50 +493,295 @@ async fn main() -> Result<(), AppError> {
             .red()
         );
     }
+    if timeouts > 0 {
+        println!(
+            "\n{}",
+            style(format!(
+                "{} items timed out; they keep any earlier judgment and are marked \"timed_out\".",
+                timeouts
+            ))
+            .red()
+        );
+    }
 
     info!("All tasks processed. Application completed.");
     Ok(())
 }
 
+/// What judging the data files of a task shares besides the task itself.
+struct JudgeContext<'a> {
+    config: &'a Config,
+    params: &'a RunParams,
+    selection: &'a SelectionConfig,
+    results_store: Option<&'a mut ResultsStore>,
+    shutdown: &'a Shutdown,
+}
+
+/// Outcome of processing one data file, shown as a row of the task summary.
+#[derive(Debug)]
+struct FileSummary {
+    path: String,
+    items: usize,
+    /// Items judged without a parseable score or failing otherwise.
+    failures: u32,
+    timeouts: u32,
+    /// Items whose judgment came from the result cache, when it is used.
+    cache_hits: Option<usize>,
+    elapsed: Duration,
+}
+
+/// Replaces the "fetch" placeholder in a task with the downloaded default
+/// dataset or rubric. Offline, nothing is downloaded and the files that are
+/// not cached yet are returned instead.
+async fn resolve_fetch_sources(
+    task_config: &mut TaskConfig,
+    data_dir: &str,
+    rubrics_dir: &str,
+    sources: &FetchSources,
+    offline: bool,
+) -> Result<Vec<String>, AppError> {
+    let mut missing = Vec::new();
+    for data in &mut task_config.data {
+        if data == "fetch" {
+            let path = format!("{}/subquery-data.json", data_dir);
+            if !Path::new(&path).exists() {
+                if offline {
+                    missing.push(format!("default dataset: {}", path));
+                } else {
+                    download_file(&sources.client, &sources.data, &path).await?;
+                }
+            }
+            *data = path;
+        }
+    }
+
+    if task_config.rubric_template == "fetch" {
+        let path = Path::new(rubrics_dir).join("subquery-decomp.jinja");
+        if !path.exists() {
+            if offline {
+                missing.push(format!("default rubric: {}", path.display()));
+            } else {
+                download_file(&sources.client, &sources.rubric, path.to_str().unwrap()).await?;
+            }
+        }
+        task_config.rubric_template = path.to_str().unwrap().to_string();
+    }
+
+    Ok(missing)
+}
+
+/// Maps each data file of a task to the file its judgments are written to.
+fn output_paths(task_config: &TaskConfig, data_files: &[String]) -> Result<Vec<String>, AppError> {
+    let Some(output) = &task_config.output else {
+        return Ok(data_files.to_vec());
+    };
+
+    if data_files.len() == 1 && !Path::new(output).is_dir() {
+        return Ok(vec![output.clone()]);
+    }
+
+    std::fs::create_dir_all(output)?;
+    let mut seen = std::collections::HashSet::new();
+    data_files
+        .iter()
+        .map(|data_path| {
+            let file_name = Path::new(data_path).file_name().ok_or_else(|| {
+                AppError::ConfigError(format!("Data path has no file name: {}", data_path))
+            })?;
+            if !seen.insert(file_name.to_owned()) {
+                return Err(AppError::ConfigError(format!(
+                    "More than one data file is named '{}' for output directory '{}'",
+                    file_name.to_string_lossy(),
+                    output
+                )));
+            }
+            Ok(Path::new(output).join(file_name).to_string_lossy().into_owned())
+        })
+        .collect()
+}
+
 async fn process_task(
     task_config: &TaskConfig,
-    config: &Config,
     batch_size: usize,
-    args: &Args,
-) -> Result<(u32, String), AppError> {
-    let data = fs::read_to_string(&task_config.data).await?;
+    context: &mut JudgeContext<'_>,
+) -> Result<(u32, u32, String), AppError> {
+    let data_files = task_config.data_files()?;
+    let output_files = output_paths(task_config, &data_files)?;
+    let mut summaries = Vec::with_capacity(data_files.len());
+    let mut last_result = String::new();
 
-    if data.trim().is_empty() {
-        return Err(AppError::CustomError("Data file is empty".to_string()));
+    for (data_path, output_path) in data_files.iter().zip(&output_files) {
+        info!("Processing data file: {}", data_path);
+        let (summary, result) =
+            process_file(data_path, output_path, task_config, batch_size, context).await?;
+        summaries.push(summary);
+        if !result.is_empty() {
+            last_result = result;
+        }
+        if context.shutdown.is_stopping() {
+            break;
+        }
     }
 
-    let file_format = detect_file_type(&task_config.data)?;
+    print_task_summary(&summaries);
 
-    let mut items: Vec<IoItem> = match file_format.as_str() {
-        "json" => read_json(&task_config.data)?,
-        "csv" => read_csv(&task_config.data)?,
-        _ => {
-            return Err(AppError::ConfigError(format!(
-                "Unsupported file format: {}",
-                file_format
-            )))
-        }
+    let parsing_failures = summaries.iter().map(|summary| summary.failures).sum();
+    let timeouts = summaries.iter().map(|summary| summary.timeouts).sum();
+    Ok((parsing_failures, timeouts, last_result))
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn print_task_summary(summaries: &[FileSummary]) {
+    let total = FileSummary {
+        path: "Total".to_string(),
+        items: summaries.iter().map(|summary| summary.items).sum(),
+        failures: summaries.iter().map(|summary| summary.failures).sum(),
+        timeouts: summaries.iter().map(|summary| summary.timeouts).sum(),
+        cache_hits: summaries
+            .iter()
+            .filter_map(|summary| summary.cache_hits)
+            .reduce(|total, hits| total + hits),
+        elapsed: summaries.iter().map(|summary| summary.elapsed).sum(),
+    };
+    let width = summaries
+        .iter()
+        .map(|summary| summary.path.chars().count())
+        .chain(std::iter::once("Results saved in".len()))
+        .max()
+        .unwrap_or_default();
+    let rule = |left: &str, mid: &str, right: &str| {
+        println!(
+            "{}{}{}{}{}{}{}{}{}{}{}",
+            left,
+            "─".repeat(width + 2),
+            mid,
+            "─".repeat(11),
+            mid,
+            "─".repeat(8),
+            mid,
+            "─".repeat(11),
+            mid,
+            "─".repeat(14),
+            right
+        );
+    };
+    let row = |summary: &FileSummary| {
+        println!(
+            "│ {:<width$} │ {:>9} │ {:>6} │ {:>9} │ {:>12} │",
+            summary.path,
+            summary.items - summary.failures as usize - summary.timeouts as usize,
+            summary.failures,
+            summary.timeouts,
+            format!("{:.2} s", summary.elapsed.as_secs_f64()),
+            width = width
+        );
     };
 
-    let total_items = items.len();
+    println!("\n\n{}", style("Task Summary:").yellow().bold());
+    rule("┌", "┬", "┐");
+    println!(
+        "│ {:<width$} │ {:>9} │ {:>6} │ {:>9} │ {:>12} │",
+        "Results saved in",
+        "Processed",
+        "Failed",
+        "Timed out",
+        "Time taken",
+        width = width
+    );
+    rule("├", "┼", "┤");
+    for summary in summaries {
+        row(summary);
+    }
+    if summaries.len() > 1 {
+        rule("├", "┼", "┤");
+        row(&total);
+    }
+    rule("└", "┴", "┘");
+
+    if total.failures > 0 {
+        println!(
+            "{}",
+            style(format!("Failed items: {}", total.failures)).yellow()
+        );
+    }
+    if let Some(hits) = total.cache_hits {
+        println!(
+            "{}",
+            style(format!(
+                "Result cache: {} of {} items reused ({:.1}% hit rate)",
+                hits,
+                total.items,
+                if total.items == 0 {
+                    0.0
+                } else {
+                    hits as f64 * 100.0 / total.items as f64
+                }
+            ))
+            .dim()
+        );
+    }
+}
+
+async fn process_file(
+    data_path: &str,
+    output_path: &str,
+    task_config: &TaskConfig,
+    batch_size: usize,
+    context: &mut JudgeContext<'_>,
+) -> Result<(FileSummary, String), AppError> {
+    let (config, params, selection, shutdown) = (
+        context.config,
+        context.params,
+        context.selection,
+        context.shutdown,
+    );
+    let data = fs::read_to_string(data_path).await?;
+
+    if data.trim().is_empty() {
+        return Err(AppError::CustomError(format!(
+            "Data file is empty: {}",
+            data_path
+        )));
+    }
+
+    let mut items = read_items(data_path)?;
+
+    // Only the selected items are judged; the rest are written back unchanged
+    let selected = selection::select(&items, selection)?;
+    let mut is_selected = vec![false; items.len()];
+    for &index in &selected {
+        is_selected[index] = true;
+    }
+
+    let total_items = selected.len();
     let concurrent_batch_size = batch_size;
 
+    // The thread budget is split across the llamafile processes running at once
+    let workers = concurrent_batch_size.min(total_items).max(1);
+    let worker_params = &RunParams {
+        thread_count: Some(threads_per_worker(params.thread_count, workers)),
+        ..params.clone()
+    };
+
     println!(
         "\n{}",
-        style(format!("Processing: {} entries", total_items))
-            .yellow()
-            .bold()
+        style(if total_items == items.len() {
+            format!("Processing: {} entries", total_items)
+        } else {
+            format!("Processing: {} of {} entries", total_items, items.len())
+        })
+        .yellow()
+        .bold()
     );
     println!(
         "{}",
-        style(format!("Concurrent batch size: {}", concurrent_batch_size))
-            .yellow()
-            .italic()
+        style(format!(
+            "Concurrent batch size: {} ({} threads each)",
+            concurrent_batch_size,
+            worker_params.thread_count.unwrap_or_default()
+        ))
+        .yellow()
+        .italic()
     );
 
     println!(); // Add an empty line for spacing
@@ -395,43 +853,143 @@ async fn process_task(
     let start_time = Instant::now();
     let parsing_failures = Arc::new(Mutex::new(0u32));
     let last_result = Arc::new(Mutex::new(String::new()));
+    let judgments = Arc::new(Mutex::new(Vec::new()));
 
     let rubric = load_rubric(&task_config.rubric_template).await?;
+    let llamafile_path = llamafile_path(config, params);
+    let result_cache = ResultCache::open(config, &llamafile_path)?;
+
+    let run_id = match &context.results_store {
+        Some(store) => Some(store.start_run(&RunRecord {
+            data_path: data_path.to_string(),
+            rubric_path: task_config.rubric_template.clone(),
+            rubric_hash: sha256_hex(rubric.as_bytes()),
+            model_id: params
+                .model
+                .clone()
+                .unwrap_or_else(|| config.llamafile_url.clone()),
+            temperature: params.temperature,
+            max_tokens: params.max_tokens,
+            context_size: params.context_size,
+            gpu_layers: params.gpu_layers,
+            thread_count: params.thread_count,
+            llamafile_kvargs: params.llamafile_kvargs.clone(),
+            item_count: total_items,
+            seed: params.seed,
+            timeout: params.timeout,
+        })?),
+        None => None,
+    };
 
     // Process all items in the JSON array concurrently, limited to concurrent_batch_size at a time
-    let results: Vec<Result<(), AppError>> = stream::iter(items.iter_mut().enumerate())
-        .map(|(index, item)| {
+    let selected_items = items
+        .iter_mut()
+        .enumerate()
+        .filter(|(index, _)| is_selected[*index])
+        .enumerate();
+    // After a shutdown request no further items are started
+    let results: Vec<Result<(), AppError>> = stream::iter(selected_items)
+        .take_while(|_| future::ready(!shutdown.is_stopping()))
+        .map(|(position, (index, item))| {
             let rubric_clone = rubric.clone();
-            let item_progress = item_progress_bars[index % concurrent_batch_size].clone();
+            let item_progress = item_progress_bars[position % concurrent_batch_size].clone();
             item_progress.set_message(
-                style(format!("Item {}/{} - Processing", index + 1, total_items))
+                style(format!("Item {}/{} - Processing", position + 1, total_items))
                     .dim()
                     .bold()
                     .to_string(),
             );
             let main_progress_bar = main_progress_bar.clone();
 
-            // FIXME: to be implemented
-            let _parsing_failures = Arc::clone(&parsing_failures);
+            let parsing_failures = Arc::clone(&parsing_failures);
             let last_result = Arc::clone(&last_result);
+            let judgments = Arc::clone(&judgments);
+            let llamafile_path = &llamafile_path;
+            let result_cache = result_cache.as_ref();
 
             async move {
-                let input = item.input.clone();
-                let output = item.output.clone();
+                let item_start = Instant::now();
+                let input = item.input().unwrap_or_default().to_string();
+                let output = item.output().unwrap_or_default().to_string();
                 let context = context! {
-                    input => input,
-                    output => output,
+                    input => input.as_str(),
+                    output => output.as_str(),
+                };
+
+                // Reuse a cached judgment of the same prompt, otherwise
+                // execute llamafile with the populated template
+                let outcome = match populate_template(&rubric_clone, &context) {
+                    Ok(populated_template) => {
+                        let key = result_cache.map(|cache| cache.key(&populated_template, params));
+                        let cached = result_cache
+                            .zip(key.as_deref())
+                            .and_then(|(cache, key)| cache.get(key));
+                        if let Some(output) = cached {
+                            Ok(LlamafileRun {
+                                output,
+                                attempts: 0,
+                                tokens: None,
+                            })
+                        } else {
+                            let run = tokio::select! {
+                                run = execute_llamafile_with_timeout(
+                                    &populated_template,
+                                    llamafile_path,
+                                    worker_params,
+                                ) => run.map_err(|e| (e, MAX_RETRIES)),
+                                () = shutdown.cancelled() => Err((
+                                    AppError::InterruptedError(
+                                        "cancelled before it was judged".to_string(),
+                                    ),
+                                    0,
+                                )),
+                            };
+                            if let (Ok(run), Some(cache), Some(key)) = (&run, result_cache, &key)
+                            {
+                                cache.put(key, &run.output);
+                            }
+                            run
+                        }
+                    }
+                    Err(e) => Err((e, 0)),
                 };
-                let populated_template = populate_template(&rubric_clone, &context)?;
 
-                // Execute llamafile with the populated template
-                let llamafile_output = execute_llamafile_with_retries(
-                    &populated_template,
-                    MAX_RETRIES,
-                    &config.cache_dir,
-                    args,
-                )
-                .await?;
+                let llamafile_run = match outcome {
+                    Ok(run) => run,
+                    Err((e, attempts)) => {
+                        judgments.lock().await.push(JudgmentRecord {
+                            item_index: index,
+                            fields: serde_json::to_string(&item.fields)?,
+                            input,
+                            output,
+                            raw_output: None,
+                            feedback: None,
+                            score: None,
+                            latency_ms: item_start.elapsed().as_millis(),
+                            attempts,
+                            error: Some(e.to_string()),
+                        });
+                        if let AppError::TimeoutError(_) = e {
+                            // An earlier judgment is kept; the mark tells the timeout apart
+                            item.set_timed_out(true);
+                            item_progress.finish_with_message(format!(
+                                "{} {}",
+                                style("⏱").yellow(),
+                                style(format!("Item {}/{} - Timed out", position + 1, total_items))
+                                    .dim()
+                                    .bold()
+                            ));
+                            main_progress_bar.inc(1);
+                        }
+                        return Err(e);
+                    }
+                };
+                let status = if llamafile_run.attempts == 0 {
+                    "Cached"
+                } else {
+                    "Completed"
+                };
+                let llamafile_output = llamafile_run.output;
 
                 // Log the llamafile output for debugging
                 debug!(
@@ -440,33 +998,32 @@ async fn process_task(
                     llamafile_output
                 );
 
-                item.feedback = Some(llamafile_output.trim().to_string());
-
-                match SCORE_REGEX.captures(&llamafile_output) {
-                    Some(captures) => {
-                        if let Some(score_match) = captures.get(1) {
-                            if let Ok(score_num) = score_match.as_str().trim().parse::<i32>() {
-                                debug!("Extracted score: {}", score_num);
-                                item.score = Some(score_num);
-                            } else {
-                                error!("Failed to parse score as integer");
-                                item.score = None;
-                            }
-                        } else {
-                            error!("Score regex matched but couldn't extract content");
-                            item.score = None;
-                        }
-                    }
-                    None => {
-                        error!("No score found in llamafile output");
-                        item.score = None;
-                    }
+                item.set_feedback(Some(llamafile_output.trim().to_string()));
+
+                let score = parse_score(&llamafile_output);
+                item.set_score(score);
+                item.set_timed_out(false);
+                if score.is_none() {
+                    *parsing_failures.lock().await += 1;
                 }
 
+                judgments.lock().await.push(JudgmentRecord {
+                    item_index: index,
+                    fields: serde_json::to_string(&item.fields)?,
+                    input,
+                    output,
+                    raw_output: Some(llamafile_output.clone()),
+                    feedback: parse_feedback(&llamafile_output),
+                    score,
+                    latency_ms: item_start.elapsed().as_millis(),
+                    attempts: llamafile_run.attempts,
+                    error: None,
+                });
+
                 item_progress.finish_with_message(format!(
                     "{} {}",
                     style("✅").green(),
-                    style(format!("Item {}/{} - Completed", index + 1, total_items))
+                    style(format!("Item {}/{} - {}", position + 1, total_items, status))
                         .dim()
                         .bold()
                 ));
@@ -480,13 +1037,32 @@ async fn process_task(
         .collect()
         .await;
 
-    // Handle errors
+    // Handle errors; items cancelled by a shutdown are left as they were
+    let started = results.len();
+    let mut cancelled = 0;
+    let mut timeouts = 0;
     for result in results {
-        if let Err(e) = result {
-            println!("{}", style(format!("Error processing item: {:?}", e)).red());
-            *parsing_failures.lock().await += 1;
+        match result {
+            Err(AppError::InterruptedError(_)) => cancelled += 1,
+            Err(AppError::TimeoutError(_)) => timeouts += 1,
+            Err(e) => {
+                println!("{}", style(format!("Error processing item: {:?}", e)).red());
+                *parsing_failures.lock().await += 1;
+            }
+            Ok(()) => {}
         }
     }
+    let judged_items = started - cancelled;
+    if judged_items < total_items {
+        println!(
+            "{}",
+            style(format!(
+                "Stopped early: {} of {} items processed, the rest are left unchanged",
+                judged_items, total_items
+            ))
+            .yellow()
+        );
+    }
 
     // Clear all individual progress bars
     // for pb in item_progress_bars {
@@ -498,41 +1074,29 @@ async fn process_task(
     let parsing_failures = *parsing_failures.lock().await;
     let last_result = last_result.lock().await.clone();
 
-    // Write the updated JSON data back to the file
-    match file_format.as_str() {
-        "json" => write_json(&items, &task_config.data)?,
-        "csv" => write_csv(&items, &task_config.data)?,
-        _ => {
-            return Err(AppError::ConfigError(format!(
-                "Unsupported file format for saving: {}",
-                file_format
-            )))
-        }
+    if let (Some(store), Some(run_id)) = (context.results_store.as_deref_mut(), run_id) {
+        let mut judgments = std::mem::take(&mut *judgments.lock().await);
+        judgments.sort_by_key(|judgment| judgment.item_index);
+        store.record_judgments(run_id, &judgments)?;
+        store.finish_run(run_id, parsing_failures)?;
     }
 
-    println!("\n\n{}", style("Task Summary:").yellow().bold());
-    println!("┌─────────────────┬────────────────────────────────┐");
-    println!("│ Metric          │ Value                          │");
-    println!("├─────────────────┼────────────────────────────────┤");
-    println!(
-        "│ Time taken      │ {:<30} │",
-        format!("{:.2} seconds", elapsed.as_secs_f64())
-    );
-    println!(
-        "│ Processed       │ {:<30} │",
-        format!("{} items", total_items - parsing_failures as usize)
-    );
-    println!("│ Results saved in│ {:<30} │", task_config.data);
-    println!("└─────────────────┴────────────────────────────────┘");
-
-    if parsing_failures > 0 {
-        println!(
-            "{}",
-            style(format!("Failed items: {}", parsing_failures)).yellow()
-        );
+    if output_path == data_path {
+        backup_original(data_path, config.backup)?;
     }
 
-    Ok((parsing_failures, last_result))
+    // Write the judged items back, converting between formats if needed
+    write_items(&items, output_path)?;
+
+    let summary = FileSummary {
+        path: output_path.to_string(),
+        items: judged_items,
+        failures: parsing_failures,
+        timeouts,
+        cache_hits: result_cache.as_ref().map(ResultCache::hits),
+        elapsed,
+    };
+    Ok((summary, last_result))
 }
 
 async fn load_rubric(rubric_template: &str) -> Result<String, AppError> {
@@ -592,49 +1156,91 @@ pub async fn update_json_file(
         ));
     }
 
-    let updated_content = serde_json::to_string_pretty(&json)?;
-    fs::write(file_path, updated_content.as_bytes()).await?;
+    write_atomic(file_path, |file| {
+        serde_json::to_writer_pretty(file, &json).map_err(AppError::from)
+    })
+}
 
-    Ok(())
+/// Output of a successful llamafile execution.
+#[derive(Debug)]
+pub struct LlamafileRun {
+    pub output: String,
+    pub attempts: u32,
+    /// Tokens generated, as reported in the llamafile timings.
+    pub tokens: Option<usize>,
+}
+
+/// The llamafile a task runs: its `model` override or the downloaded Flow-Judge model.
+fn llamafile_path(config: &Config, params: &RunParams) -> PathBuf {
+    params.model.as_ref().map_or_else(
+        || PathBuf::from(&config.cache_dir).join(cache::LLAMAFILE_NAME),
+        PathBuf::from,
+    )
+}
+
+/// Number of threads the machine can run at once.
+pub fn available_threads() -> usize {
+    thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+}
+
+/// Threads each of `workers` concurrent llamafile processes runs with:
+/// `thread_count` when set, otherwise an even share of the available threads,
+/// so concurrent processes do not compete for the same cores.
+#[must_use]
+pub fn threads_per_worker(thread_count: Option<usize>, workers: usize) -> usize {
+    thread_count.unwrap_or_else(|| (available_threads() / workers.max(1)).max(1))
+}
+
+/// Reads the number of generated tokens from the timings llamafile prints
+/// to stderr (`eval time = ... ms / N runs`).
+#[must_use]
+pub fn parse_generated_tokens(stderr: &str) -> Option<usize> {
+    EVAL_TIMING_REGEX
+        .captures_iter(stderr)
+        .last()
+        .and_then(|captures| captures[1].parse().ok())
 }
 
 pub async fn execute_llamafile_with_retries(
     input: &str,
     max_retries: u32,
-    cache_dir: &str,
-    args: &Args,
-) -> Result<String, AppError> {
-    fs::create_dir_all(cache_dir).await?;
-
-    let llamafile_path = PathBuf::from(cache_dir).join("flow-judge.llamafile");
-
+    llamafile_path: &Path,
+    params: &RunParams,
+) -> Result<LlamafileRun, AppError> {
     // Print file information for debugging
-    let metadata = fs::metadata(&llamafile_path).await?;
+    let metadata = fs::metadata(llamafile_path).await.map_err(|e| {
+        AppError::FileReadError(format!(
+            "Failed to read llamafile '{}': {}",
+            llamafile_path.display(),
+            e
+        ))
+    })?;
     debug!("Llamafile size: {} bytes", metadata.len());
     debug!("Llamafile permissions: {:o}", metadata.permissions().mode());
     debug!("Llamafile full path: {:?}", llamafile_path);
 
-    let thread_count = args.thread_count.unwrap_or_else(|| {
-        thread::available_parallelism()
-            .map(|p| p.get())
-            .unwrap_or(1)
-    });
+    let thread_count = threads_per_worker(params.thread_count, 1);
 
     let mut llamafile_command = format!(
         "{} -c {} -ngl {} {} --nocompile --simple-io --temp {} -n {} -t {} -p \"{}\"",
         llamafile_path.display(),
-        args.context_size,
-        args.gpu_layers,
-        if args.disable_kv_offload { "-nkvo" } else { "" },
-        args.temperature,
-        args.max_tokens,
+        params.context_size,
+        params.gpu_layers,
+        if params.disable_kv_offload { "-nkvo" } else { "" },
+        params.temperature,
+        params.max_tokens,
         thread_count,
         input
     );
 
+    if let Some(seed) = params.seed {
+        llamafile_command.push_str(" --seed ");
+        llamafile_command.push_str(&seed.to_string());
+    }
+
     // Add additional llamafile arguments
-    if let Some(extra_args) = &args.llamafile_kvargs {
-        validate_llamafile_kvargs(&llamafile_path, extra_args).await?;
+    if let Some(extra_args) = &params.llamafile_kvargs {
+        validate_llamafile_kvargs(llamafile_path, extra_args).await?;
         for arg_pair in extra_args.split(',') {
             if let Some((key, value)) = arg_pair.split_once('=') {
                 llamafile_command.push_str(&format!(" --{} {}", key, value));
@@ -648,14 +1254,17 @@ pub async fn execute_llamafile_with_retries(
         let output = if cfg!(target_os = "windows") {
             tokio::process::Command::new("cmd")
                 .args(&["/C", &llamafile_command])
+                .kill_on_drop(true)
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
                 .output()
                 .await?
         } else {
+            // exec, so killing the shell kills llamafile
             tokio::process::Command::new("sh")
                 .arg("-c")
-                .arg(&llamafile_command)
+                .arg(format!("exec {}", llamafile_command))
+                .kill_on_drop(true)
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
                 .output()
@@ -664,7 +1273,11 @@ pub async fn execute_llamafile_with_retries(
 
         if output.status.success() {
             debug!("Llamafile execution successful");
-            return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+            return Ok(LlamafileRun {
+                output: String::from_utf8_lossy(&output.stdout).to_string(),
+                attempts: attempt,
+                tokens: parse_generated_tokens(&String::from_utf8_lossy(&output.stderr)),
+            });
         }
 
         let error = String::from_utf8_lossy(&output.stderr);
@@ -684,6 +1297,30 @@ pub async fn execute_llamafile_with_retries(
     ))
 }
 
+/// Runs llamafile like [`execute_llamafile_with_retries`], giving up and
+/// killing it once all attempts take longer than the configured timeout.
+///
+/// # Errors
+///
+/// Returns [`AppError::TimeoutError`] once the timeout expires and
+/// [`AppError::ConfigError`] for a timeout that is not a valid duration;
+/// otherwise the errors of [`execute_llamafile_with_retries`].
+pub async fn execute_llamafile_with_timeout(
+    input: &str,
+    llamafile_path: &Path,
+    params: &RunParams,
+) -> Result<LlamafileRun, AppError> {
+    let run = execute_llamafile_with_retries(input, MAX_RETRIES, llamafile_path, params);
+    let Some(seconds) = params.timeout else {
+        return run.await;
+    };
+    let limit = Duration::try_from_secs_f64(seconds)
+        .map_err(|_| AppError::ConfigError(format!("Invalid timeout: {}", seconds)))?;
+    tokio::time::timeout(limit, run)
+        .await
+        .map_err(|_| AppError::TimeoutError(format!("no judgment after {} s", seconds)))?
+}
+
 pub async fn fetch_rubrics(task_config: &TaskConfig) -> Result<String, AppError> {
     let rubric_path = PathBuf::from(RUBRICS_DIR).join(&task_config.rubric_template);
     let rubric_content = fs::read_to_string(&rubric_path).await?;
@@ -718,21 +1355,54 @@ pub fn populate_template(
     template.render(context).map_err(AppError::from)
 }
 
-fn save_last_result(result: &str, cache_dir: &str) -> Result<(), AppError> {
-    let result_file_path = PathBuf::from(cache_dir).join("last_result.txt");
-    let mut file = File::create(&result_file_path).map_err(|e| {
-        AppError::FileWriteError(format!("Failed to create last result file: {}", e))
-    })?;
+/// Extracts the score from the judge output.
+#[must_use]
+pub fn parse_score(llamafile_output: &str) -> Option<i32> {
+    match SCORE_REGEX.captures(llamafile_output) {
+        Some(captures) => {
+            if let Some(score_match) = captures.get(1) {
+                if let Ok(score_num) = score_match.as_str().trim().parse::<i32>() {
+                    debug!("Extracted score: {}", score_num);
+                    Some(score_num)
+                } else {
+                    error!("Failed to parse score as integer");
+                    None
+                }
+            } else {
+                error!("Score regex matched but couldn't extract content");
+                None
+            }
+        }
+        None => {
+            error!("No score found in llamafile output");
+            None
+        }
+    }
+}
 
-    file.write_all(result.as_bytes()).map_err(|e| {
-        AppError::FileWriteError(format!("Failed to write last result to file: {}", e))
-    })?;
+/// Extracts the feedback from the judge output.
+#[must_use]
+pub fn parse_feedback(llamafile_output: &str) -> Option<String> {
+    FEEDBACK_REGEX
+        .captures(llamafile_output)
+        .and_then(|captures| captures.get(1))
+        .map(|feedback| feedback.as_str().trim().to_string())
+}
 
-    Ok(())
+fn save_last_result(result: &str, cache_dir: &str) -> Result<(), AppError> {
+    std::fs::create_dir_all(cache_dir)?;
+    let result_file_path = PathBuf::from(cache_dir).join("last_result.txt");
+    let _lock = FileLock::exclusive(&result_file_path)?;
+    write_atomic(&result_file_path.to_string_lossy(), |file| {
+        file.write_all(result.as_bytes()).map_err(|e| {
+            AppError::FileWriteError(format!("Failed to write last result to file: {}", e))
+        })
+    })
 }
 
 fn read_last_result(cache_dir: &str) -> Result<String, AppError> {
     let result_file_path = PathBuf::from(cache_dir).join("last_result.txt");
+    let _lock = FileLock::shared(&result_file_path)?;
     std::fs::read_to_string(&result_file_path)
         .map_err(|e| AppError::FileReadError(format!("Failed to read last result file: {}", e)))
 }
@@ -755,16 +1425,124 @@ fn detect_file_type(file_path: &str) -> Result<String, AppError> {
     }
 }
 
-fn write_csv(items: &[IoItem], file_path: &str) -> Result<(), AppError> {
-    let file = File::create(file_path).map_err(|e| {
-        AppError::FileWriteError(format!("Failed to create file '{}': {}", file_path, e))
+/// Reads a JSON or CSV dataset, depending on the file extension.
+fn read_items(file_path: &str) -> Result<Vec<IoItem>, AppError> {
+    let file_format = detect_file_type(file_path)?;
+    match file_format.as_str() {
+        "json" => read_json(file_path),
+        "csv" => read_csv(file_path),
+        _ => Err(AppError::ConfigError(format!(
+            "Unsupported file format: {}",
+            file_format
+        ))),
+    }
+}
+
+/// Writes a JSON or CSV dataset, depending on the file extension.
+fn write_items(items: &[IoItem], file_path: &str) -> Result<(), AppError> {
+    let file_format = detect_file_type(file_path)?;
+    match file_format.as_str() {
+        "json" => write_json(items, file_path),
+        "csv" => write_csv(items, file_path),
+        _ => Err(AppError::ConfigError(format!(
+            "Unsupported file format for saving: {}",
+            file_format
+        ))),
+    }
+}
+
+/// Writes `file_path` through a temporary file in the same directory which is
+/// then renamed over the target, so an error or a kill mid-write never leaves a
+/// truncated file behind.
+fn write_atomic<F>(file_path: &str, write: F) -> Result<(), AppError>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), AppError>,
+{
+    let path = Path::new(file_path);
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let temp_file = tempfile::NamedTempFile::new_in(dir).map_err(|e| {
+        AppError::FileWriteError(format!(
+            "Failed to create temporary file next to '{}': {}",
+            file_path, e
+        ))
+    })?;
+
+    // Keep the permissions of the file being replaced
+    if let Ok(metadata) = std::fs::metadata(path) {
+        std::fs::set_permissions(temp_file.path(), metadata.permissions())?;
+    }
+
+    let mut buf_writer = BufWriter::new(temp_file.as_file());
+    write(&mut buf_writer)?;
+    buf_writer.flush().map_err(|e| {
+        AppError::FileWriteError(format!("Failed to write '{}': {}", file_path, e))
+    })?;
+    drop(buf_writer);
+
+    temp_file.as_file().sync_all()?;
+    temp_file.persist(path).map_err(|e| {
+        AppError::FileWriteError(format!("Failed to replace '{}': {}", file_path, e))
     })?;
 
+    Ok(())
+}
+
+/// Copies the dataset aside before it is modified in place for the first time.
+///
+/// A `.bak` copy is only made once, so it always holds the original dataset;
+/// timestamped copies are made on every run.
+fn backup_original(file_path: &str, mode: BackupMode) -> Result<Option<PathBuf>, AppError> {
+    let backup_path = match mode {
+        BackupMode::None => return Ok(None),
+        BackupMode::Bak => PathBuf::from(format!("{}.bak", file_path)),
+        BackupMode::Timestamped => PathBuf::from(format!(
+            "{}.{}.bak",
+            file_path,
+            chrono::Local::now().format("%Y%m%dT%H%M%S")
+        )),
+    };
+
+    if backup_path.exists() {
+        debug!("Backup {} already exists", backup_path.display());
+        return Ok(None);
+    }
+
+    std::fs::copy(file_path, &backup_path).map_err(|e| {
+        AppError::FileWriteError(format!(
+            "Failed to back up '{}' to '{}': {}",
+            file_path,
+            backup_path.display(),
+            e
+        ))
+    })?;
+    info!("Backed up {} to {}", file_path, backup_path.display());
+    Ok(Some(backup_path))
+}
+
+fn write_csv(items: &[IoItem], file_path: &str) -> Result<(), AppError> {
+    write_atomic(file_path, |file| write_csv_to(items, file))
+}
+
+fn write_csv_to(items: &[IoItem], file: &mut dyn Write) -> Result<(), AppError> {
     let mut writer = WriterBuilder::new().from_writer(file);
 
+    let columns = collect_columns(items);
+    if !columns.is_empty() {
+        writer
+            .write_record(&columns)
+            .map_err(|e| AppError::CsvWriteError(format!("Failed to write CSV header: {}", e)))?;
+    }
+
     for item in items {
+        let record = columns
+            .iter()
+            .map(|column| item.fields.get(column).map(csv_cell).unwrap_or_default());
         writer
-            .serialize(item)
+            .write_record(record)
             .map_err(|e| AppError::CsvWriteError(format!("Failed to write CSV record: {}", e)))?;
     }
 
@@ -774,6 +1552,24 @@ fn write_csv(items: &[IoItem], file_path: &str) -> Result<(), AppError> {
     Ok(())
 }
 
+/// Collects the CSV columns of all items in order of first appearance, so the
+/// original header order is kept and new judgment columns are appended.
+fn collect_columns(items: &[IoItem]) -> Vec<String> {
+    let mut columns: IndexSet<String> = IndexSet::new();
+    for item in items {
+        columns.extend(item.fields.keys().cloned());
+    }
+    columns.into_iter().collect()
+}
+
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
 fn read_csv(file_path: &str) -> Result<Vec<IoItem>, AppError> {
     let file = File::open(file_path).map_err(|e| {
         AppError::FileReadError(format!("Failed to open file '{}': {}", file_path, e))
@@ -781,20 +1577,32 @@ fn read_csv(file_path: &str) -> Result<Vec<IoItem>, AppError> {
 
     let mut reader = ReaderBuilder::new().from_reader(file);
 
-    let items: Result<Vec<IoItem>, _> = reader.deserialize().collect();
-    items.map_err(|e| AppError::CsvReadError(format!("Failed to read CSV: {}", e)))
+    let headers = reader
+        .headers()
+        .map_err(|e| AppError::CsvReadError(format!("Failed to read CSV header: {}", e)))?
+        .clone();
+
+    let mut items = Vec::new();
+    for record in reader.records() {
+        let record =
+            record.map_err(|e| AppError::CsvReadError(format!("Failed to read CSV: {}", e)))?;
+        let fields = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(column, cell)| (column.to_string(), Value::String(cell.to_string())))
+            .collect();
+        items.push(IoItem { fields });
+    }
+
+    validate_items(&items, file_path)?;
+    Ok(items)
 }
 
 fn write_json(items: &[IoItem], file_path: &str) -> Result<(), AppError> {
-    let file = File::create(file_path).map_err(|e| {
-        AppError::FileWriteError(format!("Failed to create file '{}': {}", file_path, e))
-    })?;
-    let buf_writer = BufWriter::new(file);
-
-    serde_json::to_writer_pretty(buf_writer, items)
-        .map_err(|e| AppError::JsonWriteError(format!("Failed to write JSON: {}", e)))?;
-
-    Ok(())
+    write_atomic(file_path, |file| {
+        serde_json::to_writer_pretty(file, items)
+            .map_err(|e| AppError::JsonWriteError(format!("Failed to write JSON: {}", e)))
+    })
 }
 
 fn read_json(file_path: &str) -> Result<Vec<IoItem>, AppError> {
@@ -806,24 +1614,25 @@ fn read_json(file_path: &str) -> Result<Vec<IoItem>, AppError> {
     let items: Vec<IoItem> = serde_json::from_reader(buf_reader)
         .map_err(|e| AppError::JsonParseError(format!("Failed to parse JSON: {}", e)))?;
 
-    // Ensure UTF-8 validity for all string fields
-    items
-        .into_iter()
-        .map(|item| {
-            Ok(IoItem {
-                input: ensure_utf8(&item.input)?,
-                output: ensure_utf8(&item.output)?,
-                feedback: item.feedback.map(|f| ensure_utf8(&f)).transpose()?,
-                score: item.score,
-            })
-        })
-        .collect()
+    validate_items(&items, file_path)?;
+    Ok(items)
 }
 
-fn ensure_utf8(s: &str) -> Result<String, AppError> {
-    from_utf8(s.as_bytes())
-        .map(|s| s.to_string())
-        .map_err(|e| AppError::EncodingError(format!("Invalid UTF-8 sequence: {}", e)))
+// Every record needs the 'input' and 'output' the rubric is rendered with
+fn validate_items(items: &[IoItem], file_path: &str) -> Result<(), AppError> {
+    for (index, item) in items.iter().enumerate() {
+        for field in ["input", "output"] {
+            if item.fields.get(field).and_then(Value::as_str).is_none() {
+                return Err(AppError::ParseError(format!(
+                    "Record {} in '{}' has no string field '{}'",
+                    index + 1,
+                    file_path,
+                    field
+                )));
+            }
+        }
+    }
+    Ok(())
 }
 
 async fn validate_llamafile_kvargs(llamafile_path: &Path, args: &str) -> Result<(), AppError> {
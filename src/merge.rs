@@ -0,0 +1,277 @@
+use crate::cli::OnConflict;
+use crate::models::{AppError, IoItem};
+use crate::selection::field_text;
+use crate::store::sha256_hex;
+use console::style;
+use indexmap::{IndexMap, IndexSet};
+use log::info;
+
+/// Number of conflicts and coverage gaps listed before the rest are summarised.
+const MAX_LISTED: usize = 10;
+
+/// An item judged with different scores in different files.
+#[derive(Debug)]
+pub struct Conflict {
+    pub position: usize,
+    pub key: String,
+    pub scores: Vec<(String, i32)>,
+}
+
+/// What was combined, and what could not be.
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub items: usize,
+    pub judged: usize,
+    /// Per input file: items, judged items and items of the merged dataset it lacks.
+    pub files: Vec<(String, usize, usize, usize)>,
+    pub conflicts: Vec<Conflict>,
+    /// Position, key and input of the items no file has a score for.
+    pub gaps: Vec<(usize, String, String)>,
+}
+
+/// A stable key for an item: the value of `id_field` when the item has one,
+/// otherwise a hash of its input and output.
+pub fn item_key(item: &IoItem, id_field: &str) -> String {
+    if let Some(id) = field_text(item, id_field).filter(|id| !id.is_empty()) {
+        return format!("{}={}", id_field, id);
+    }
+    let content = format!(
+        "{}\0{}",
+        item.input().unwrap_or_default(),
+        item.output().unwrap_or_default()
+    );
+    format!("sha256:{}", &sha256_hex(content.as_bytes())[..16])
+}
+
+/// Keys of all items of one file; repeated keys get an occurrence suffix so
+/// duplicate records are matched up in order.
+pub fn item_keys(items: &[IoItem], id_field: &str) -> Vec<String> {
+    keys_with(items, |item| item_key(item, id_field))
+}
+
+/// Like [`item_keys`], with the key of a single item computed by `key`.
+pub fn keys_with<F>(items: &[IoItem], key: F) -> Vec<String>
+where
+    F: Fn(&IoItem) -> String,
+{
+    let mut seen: IndexMap<String, usize> = IndexMap::new();
+    items
+        .iter()
+        .map(|item| {
+            let key = key(item);
+            let count = seen.entry(key.clone()).or_default();
+            *count += 1;
+            if *count == 1 {
+                key
+            } else {
+                format!("{}#{}", key, count)
+            }
+        })
+        .collect()
+}
+
+/// Combines judged copies of the same dataset.
+///
+/// Items keep the order in which they first appear. An item takes its
+/// judgment from whichever file scored it; when several files scored it
+/// differently, `on_conflict` decides which one is kept.
+pub fn merge(
+    sources: &[(String, Vec<IoItem>)],
+    id_field: &str,
+    on_conflict: OnConflict,
+) -> (Vec<IoItem>, MergeReport) {
+    // The first copy of each item and the scored copies, by source file
+    type Judgments<'a> = Vec<(usize, &'a IoItem)>;
+    let mut merged: IndexMap<String, (IoItem, Judgments)> = IndexMap::new();
+    let mut file_keys = Vec::with_capacity(sources.len());
+
+    for (source, (_, items)) in sources.iter().enumerate() {
+        let keys = item_keys(items, id_field);
+        for (key, item) in keys.iter().zip(items) {
+            let (_, judgments) = merged
+                .entry(key.clone())
+                .or_insert_with(|| (item.clone(), Vec::new()));
+            if item.score().is_some() {
+                judgments.push((source, item));
+            }
+        }
+        file_keys.push(keys.into_iter().collect::<IndexSet<_>>());
+    }
+
+    let mut report = MergeReport {
+        items: merged.len(),
+        ..MergeReport::default()
+    };
+    for ((path, items), keys) in sources.iter().zip(&file_keys) {
+        let judged = items.iter().filter(|item| item.score().is_some()).count();
+        report
+            .files
+            .push((path.clone(), items.len(), judged, merged.len() - keys.len()));
+    }
+
+    let mut results = Vec::with_capacity(merged.len());
+    for (position, (key, (mut item, judgments))) in merged.into_iter().enumerate() {
+        let scores: IndexSet<i32> = judgments
+            .iter()
+            .filter_map(|(_, judged)| judged.score())
+            .collect();
+        if scores.len() > 1 {
+            report.conflicts.push(Conflict {
+                position,
+                key: key.clone(),
+                scores: judgments
+                    .iter()
+                    .filter_map(|(source, judged)| {
+                        Some((sources[*source].0.clone(), judged.score()?))
+                    })
+                    .collect(),
+            });
+        }
+
+        let chosen = match on_conflict {
+            OnConflict::Last => judgments.last(),
+            OnConflict::First | OnConflict::Fail => judgments.first(),
+        };
+        match chosen {
+            Some((_, judged)) => {
+                for (field, value) in &judged.fields {
+                    item.fields.insert(field.clone(), value.clone());
+                }
+                item.set_score(judged.score());
+                report.judged += 1;
+            }
+            None => report
+                .gaps
+                .push((position, key, item.input().unwrap_or_default().to_string())),
+        }
+        results.push(item);
+    }
+
+    (results, report)
+}
+
+/// Runs `fwj merge`: reads the judged files, merges them and writes the
+/// result unless there are unresolved conflicts or required items are missing.
+pub fn run(
+    files: &[String],
+    output: &str,
+    id_field: &str,
+    on_conflict: OnConflict,
+    require_complete: bool,
+) -> Result<(), AppError> {
+    let mut sources = Vec::with_capacity(files.len());
+    for file in files {
+        info!("Reading judged items from {}", file);
+        sources.push((file.clone(), crate::read_items(file)?));
+    }
+
+    let (items, report) = merge(&sources, id_field, on_conflict);
+    print_report(&report);
+
+    if !report.conflicts.is_empty() && on_conflict == OnConflict::Fail {
+        return Err(AppError::CustomError(format!(
+            "{} item(s) have conflicting judgments; pass --on-conflict first or last to keep one",
+            report.conflicts.len()
+        )));
+    }
+    if require_complete && !report.gaps.is_empty() {
+        return Err(AppError::CustomError(format!(
+            "{} item(s) are not judged in any file",
+            report.gaps.len()
+        )));
+    }
+
+    crate::write_items(&items, output)?;
+    println!(
+        "\n{}",
+        style(format!("Merged results saved to {}", output)).green()
+    );
+    Ok(())
+}
+
+fn print_report(report: &MergeReport) {
+    println!("\n{}", style("Merge Summary:").yellow().bold());
+    for (path, items, judged, missing) in &report.files {
+        let missing = if *missing > 0 {
+            format!(", {} missing", missing)
+        } else {
+            String::new()
+        };
+        println!("  {}: {} items, {} judged{}", path, items, judged, missing);
+    }
+    println!(
+        "  {}",
+        style(format!(
+            "Merged: {} items, {} judged ({:.1}%)",
+            report.items,
+            report.judged,
+            percent(report.judged, report.items)
+        ))
+        .bold()
+    );
+
+    if !report.conflicts.is_empty() {
+        println!(
+            "\n{}",
+            style(format!(
+                "Conflicting judgments ({}):",
+                report.conflicts.len()
+            ))
+            .red()
+        );
+        for conflict in report.conflicts.iter().take(MAX_LISTED) {
+            let scores: Vec<String> = conflict
+                .scores
+                .iter()
+                .map(|(path, score)| format!("{}={}", path, score))
+                .collect();
+            println!(
+                "  item {} ({}): {}",
+                conflict.position + 1,
+                conflict.key,
+                scores.join(", ")
+            );
+        }
+        print_remaining(report.conflicts.len());
+    }
+
+    if !report.gaps.is_empty() {
+        println!(
+            "\n{}",
+            style(format!(
+                "Coverage gaps ({} items not judged in any file):",
+                report.gaps.len()
+            ))
+            .yellow()
+        );
+        for (position, key, input) in report.gaps.iter().take(MAX_LISTED) {
+            println!("  item {} ({}): {}", position + 1, key, preview(input));
+        }
+        print_remaining(report.gaps.len());
+    }
+}
+
+fn print_remaining(count: usize) {
+    if count > MAX_LISTED {
+        println!("  ... and {} more", count - MAX_LISTED);
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+/// The first line of `text`, shortened to 60 characters.
+pub fn preview(text: &str) -> String {
+    let line = text.lines().next().unwrap_or_default();
+    if line.chars().count() > 60 || text.lines().nth(1).is_some() {
+        format!("{}...", line.chars().take(60).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
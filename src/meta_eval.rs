@@ -0,0 +1,280 @@
+use crate::cli::ReportFormat;
+use crate::merge::preview;
+use crate::models::{AppError, IoItem};
+use crate::report::render_table;
+use crate::stats::{cohen_kappa, kendall_tau_b, spearman};
+use console::style;
+use serde::Serialize;
+
+/// An item the judge scored differently from the human rater.
+#[derive(Debug, Serialize)]
+pub struct Disagreement {
+    /// Position of the item in the file, starting at 1.
+    pub item: usize,
+    pub human: i32,
+    pub judge: i32,
+    pub input: String,
+    pub feedback: String,
+}
+
+/// Agreement between the judge scores and human labels of a dataset.
+#[derive(Debug, Serialize)]
+pub struct MetaEval {
+    pub file: String,
+    pub human_column: String,
+    pub judge_column: String,
+    pub items: usize,
+    /// Items with both a human label and a judge score.
+    pub paired: usize,
+    pub exact_match: f64,
+    pub within_one: f64,
+    pub cohen_kappa: Option<f64>,
+    pub quadratic_kappa: Option<f64>,
+    pub spearman: Option<f64>,
+    pub kendall_tau: Option<f64>,
+    /// Labels of the confusion matrix rows (human) and columns (judge).
+    pub categories: Vec<i32>,
+    pub confusion: Vec<Vec<usize>>,
+    pub disagreements: Vec<Disagreement>,
+}
+
+/// Compares `judge_column` against `human_column` over the items that have
+/// both, listing up to `top` of the largest disagreements.
+#[allow(clippy::cast_precision_loss)]
+pub fn evaluate(
+    file: &str,
+    items: &[IoItem],
+    human_column: &str,
+    judge_column: &str,
+    top: usize,
+) -> MetaEval {
+    let pairs: Vec<(usize, i32, i32)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| {
+            Some((
+                index,
+                item.int_field(human_column)?,
+                item.int_field(judge_column)?,
+            ))
+        })
+        .collect();
+    let paired = pairs.len();
+    let ratings: Vec<(i32, i32)> = pairs.iter().map(|(_, h, j)| (*h, *j)).collect();
+    let human: Vec<f64> = ratings.iter().map(|(h, _)| f64::from(*h)).collect();
+    let judge: Vec<f64> = ratings.iter().map(|(_, j)| f64::from(*j)).collect();
+
+    // Every score between the lowest and highest, so a score nobody gave
+    // still counts towards the distance between its neighbours
+    let scores = ratings.iter().flat_map(|(h, j)| [*h, *j]);
+    let categories: Vec<i32> = match (scores.clone().min(), scores.max()) {
+        (Some(min), Some(max)) => (min..=max).collect(),
+        _ => Vec::new(),
+    };
+    let mut confusion = vec![vec![0; categories.len()]; categories.len()];
+    for (h, j) in &ratings {
+        let row = categories.binary_search(h).unwrap_or_default();
+        let column = categories.binary_search(j).unwrap_or_default();
+        confusion[row][column] += 1;
+    }
+
+    let rate = |count: usize| {
+        if paired == 0 {
+            0.0
+        } else {
+            count as f64 / paired as f64
+        }
+    };
+
+    let mut disagreements: Vec<&(usize, i32, i32)> =
+        pairs.iter().filter(|(_, h, j)| h != j).collect();
+    disagreements.sort_by_key(|(index, h, j)| (std::cmp::Reverse((h - j).abs()), *index));
+
+    MetaEval {
+        file: file.to_string(),
+        human_column: human_column.to_string(),
+        judge_column: judge_column.to_string(),
+        items: items.len(),
+        paired,
+        exact_match: rate(ratings.iter().filter(|(h, j)| h == j).count()),
+        within_one: rate(ratings.iter().filter(|(h, j)| (h - j).abs() <= 1).count()),
+        cohen_kappa: cohen_kappa(&ratings, &categories, false),
+        quadratic_kappa: cohen_kappa(&ratings, &categories, true),
+        spearman: spearman(&human, &judge),
+        kendall_tau: kendall_tau_b(&human, &judge),
+        categories,
+        confusion,
+        disagreements: disagreements
+            .into_iter()
+            .take(top)
+            .map(|(index, h, j)| Disagreement {
+                item: index + 1,
+                human: *h,
+                judge: *j,
+                input: items[*index].input().unwrap_or_default().to_string(),
+                feedback: items[*index].feedback().unwrap_or_default().to_string(),
+            })
+            .collect(),
+    }
+}
+
+/// Runs `fwj meta-eval`: reads a judged file with human labels and prints
+/// how well the judge agrees with them.
+pub fn run(
+    file: &str,
+    human_column: &str,
+    judge_column: &str,
+    top: usize,
+    format: ReportFormat,
+) -> Result<(), AppError> {
+    let items = crate::read_items(file)?;
+    let result = evaluate(file, &items, human_column, judge_column, top);
+    if result.paired == 0 {
+        return Err(AppError::CustomError(format!(
+            "No item in '{}' has both a '{}' and a '{}' value",
+            file, human_column, judge_column
+        )));
+    }
+
+    match format {
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&result)?),
+        ReportFormat::Table => print_table(&result),
+        ReportFormat::Markdown => print!("{}", to_markdown(&result)),
+    }
+    Ok(())
+}
+
+fn format_metric(value: Option<f64>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| format!("{:.3}", value))
+}
+
+fn metric_rows(result: &MetaEval) -> Vec<Vec<String>> {
+    vec![
+        vec![
+            "Paired items".to_string(),
+            format!("{} of {}", result.paired, result.items),
+        ],
+        vec![
+            "Exact match".to_string(),
+            format!("{:.1}%", result.exact_match * 100.0),
+        ],
+        vec![
+            "Within one point".to_string(),
+            format!("{:.1}%", result.within_one * 100.0),
+        ],
+        vec![
+            "Cohen's kappa".to_string(),
+            format_metric(result.cohen_kappa),
+        ],
+        vec![
+            "Quadratic-weighted kappa".to_string(),
+            format_metric(result.quadratic_kappa),
+        ],
+        vec!["Spearman's rho".to_string(), format_metric(result.spearman)],
+        vec![
+            "Kendall's tau-b".to_string(),
+            format_metric(result.kendall_tau),
+        ],
+    ]
+}
+
+/// The confusion matrix as a table, human labels down and judge scores across.
+fn confusion_table(result: &MetaEval, markdown: bool) -> String {
+    let mut headers = vec![format!(
+        "{} ↓ {} →",
+        result.human_column, result.judge_column
+    )];
+    headers.extend(result.categories.iter().map(ToString::to_string));
+    let rows: Vec<Vec<String>> = result
+        .categories
+        .iter()
+        .zip(&result.confusion)
+        .map(|(category, counts)| {
+            std::iter::once(category.to_string())
+                .chain(counts.iter().map(ToString::to_string))
+                .collect()
+        })
+        .collect();
+    render_table(&headers, &rows, markdown)
+}
+
+fn disagreement_rows(result: &MetaEval) -> Vec<Vec<String>> {
+    result
+        .disagreements
+        .iter()
+        .map(|d| {
+            vec![
+                d.item.to_string(),
+                d.human.to_string(),
+                d.judge.to_string(),
+                preview(&d.input),
+                preview(&d.feedback),
+            ]
+        })
+        .collect()
+}
+
+fn disagreement_headers(result: &MetaEval) -> Vec<String> {
+    vec![
+        "Item".to_string(),
+        result.human_column.clone(),
+        result.judge_column.clone(),
+        "Input".to_string(),
+        "Feedback".to_string(),
+    ]
+}
+
+fn print_table(result: &MetaEval) {
+    println!(
+        "\n{}",
+        style(format!(
+            "Agreement of '{}' with '{}' in {}",
+            result.judge_column, result.human_column, result.file
+        ))
+        .yellow()
+        .bold()
+    );
+    let rows = metric_rows(result);
+    let width = rows.iter().map(|row| row[0].len()).max().unwrap_or(0);
+    for row in rows {
+        println!("  {:<width$}  {}", row[0], row[1], width = width);
+    }
+
+    println!("\n{}", style("Confusion matrix:").bold());
+    print!("{}", confusion_table(result, false));
+
+    if !result.disagreements.is_empty() {
+        println!("\n{}", style("Largest disagreements:").bold());
+        print!(
+            "{}",
+            render_table(
+                &disagreement_headers(result),
+                &disagreement_rows(result),
+                false
+            )
+        );
+    }
+}
+
+fn to_markdown(result: &MetaEval) -> String {
+    let mut markdown = format!(
+        "## Agreement of `{}` with `{}` in `{}`\n\n",
+        result.judge_column, result.human_column, result.file
+    );
+    markdown.push_str(&render_table(
+        &["Metric".to_string(), "Value".to_string()],
+        &metric_rows(result),
+        true,
+    ));
+    markdown.push_str("\n### Confusion matrix\n\n");
+    markdown.push_str(&confusion_table(result, true));
+    if !result.disagreements.is_empty() {
+        markdown.push_str("\n### Largest disagreements\n\n");
+        markdown.push_str(&render_table(
+            &disagreement_headers(result),
+            &disagreement_rows(result),
+            true,
+        ));
+    }
+    markdown
+}
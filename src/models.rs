@@ -1,7 +1,9 @@
 use dirs;
+use indexmap::IndexMap;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use tokio::sync::Mutex;
@@ -10,11 +12,15 @@ use thiserror::Error;
 // Constants
 pub const LLAMAFILE_URL: &str =
     "https://huggingface.co/sariola/flow-judge-llamafile/resolve/main/flow-judge.llamafile";
-pub const LLAMAFILE_LOCK_URL: &str =
-    "https://huggingface.co/sariola/flow-judge-llamafile/raw/main/flow-judge.llamafile";
 pub const MAX_RETRIES: u32 = 3;
+pub const DEFAULT_CONTEXT_SIZE: usize = 8192;
+pub const DEFAULT_GPU_LAYERS: usize = 34;
+pub const DEFAULT_TEMPERATURE: f64 = 0.1;
+pub const DEFAULT_MAX_TOKENS: usize = 1000;
 pub const SCORE_REGEX_PATTERN: &str = r"<score>\s*(\d+)\s*</score>";
 pub const FEEDBACK_REGEX_PATTERN: &str = r"(?s)<feedback>(.+?)</feedback>";
+/// The generation timing llamafile prints, not to be confused with `prompt eval time`.
+pub const EVAL_TIMING_REGEX_PATTERN: &str = r":\s+eval time\s*=\s*[\d.]+ ms\s*/\s*(\d+) (?:runs|tokens)";
 pub const RUBRICS_DIR: &str = "./rubrics";
 pub const DATA_DIR: &str = "./data";
 pub const DATA_URL: &str =
@@ -26,6 +32,7 @@ lazy_static! {
     pub static ref FILE_LOCKS: Mutex<HashMap<String, Mutex<()>>> = Mutex::new(HashMap::new());
     pub static ref SCORE_REGEX: Regex = Regex::new(SCORE_REGEX_PATTERN).unwrap();
     pub static ref FEEDBACK_REGEX: Regex = Regex::new(FEEDBACK_REGEX_PATTERN).unwrap();
+    pub static ref EVAL_TIMING_REGEX: Regex = Regex::new(EVAL_TIMING_REGEX_PATTERN).unwrap();
 }
 
 #[derive(Debug, Error)]
@@ -68,15 +75,26 @@ pub enum AppError {
     ParseError(String),
     #[error("Download error: {0}")]
     DownloadError(String),
+    #[error("Offline mode: {0}")]
+    OfflineError(String),
+    #[error("Bundle error: {0}")]
+    BundleError(String),
+    #[error("Timed out: {0}")]
+    TimeoutError(String),
+    #[error("Interrupted: {0}")]
+    InterruptedError(String),
     #[error("CSV parse error: {0}")]
     CsvParseError(String),
     #[error("Encoding error: {0}")]
     EncodingError(String),
+    #[error("SQLite error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
     #[error("Anyhow error: {0}")]
     AnyhowError(#[from] anyhow::Error),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
     #[serde(default)]
     pub tasks: Vec<TaskConfig>,
@@ -88,6 +106,157 @@ pub struct Config {
     pub rubrics_dir: String,
     #[serde(default = "default_data_dir")]
     pub data_dir: String,
+    /// Lock file (Git LFS pointer) of the llamafile; derived from `llamafile_url` when unset
+    #[serde(default)]
+    pub lock_url: Option<String>,
+    /// Where the default dataset of `data: fetch` comes from
+    #[serde(default = "default_data_url")]
+    pub data_url: String,
+    /// Where the default rubric of `rubric_template: fetch` comes from
+    #[serde(default = "default_rubric_url")]
+    pub rubric_url: String,
+    /// Mirror replacing `https://huggingface.co` in model sources (`HF_ENDPOINT`)
+    #[serde(default)]
+    pub hf_endpoint: Option<String>,
+    /// Proxy for all downloads, instead of `HTTPS_PROXY` and friends
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// PEM file with extra CA certificates to trust for downloads
+    #[serde(default)]
+    pub ca_bundle: Option<String>,
+    /// Optional `SQLite` database that every run is also recorded into
+    #[serde(default)]
+    pub results_db: Option<String>,
+    /// Copy each dataset aside before it is first modified in place
+    #[serde(default)]
+    pub backup: BackupMode,
+    /// Sampling and llamafile options shared by all tasks
+    #[serde(flatten)]
+    pub run: RunConfig,
+    /// Which items of each dataset to judge
+    #[serde(flatten)]
+    pub selection: SelectionConfig,
+    /// Named bundles of options, selected with `--profile`
+    #[serde(default)]
+    pub profiles: IndexMap<String, Profile>,
+    /// Profile used when `--profile` is not given
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Never wait for input, e.g. in CI jobs (`--yes`, `FWJ_NONINTERACTIVE=1`)
+    #[serde(default, deserialize_with = "flag")]
+    pub noninteractive: bool,
+    /// Only use cached artifacts and never touch the network (`--offline`, `FWJ_OFFLINE=1`)
+    #[serde(default, deserialize_with = "flag")]
+    pub offline: bool,
+    /// Neither reuse nor store judgments in the result cache (`--no-cache`)
+    #[serde(default, deserialize_with = "flag")]
+    pub no_cache: bool,
+    /// Judge every item again, replacing its cached judgment (`--refresh`)
+    #[serde(default, deserialize_with = "flag")]
+    pub refresh: bool,
+}
+
+impl Config {
+    /// Looks up the selected profile, if any.
+    pub fn active_profile(&self) -> Result<Option<&Profile>, AppError> {
+        let Some(name) = &self.profile else {
+            return Ok(None);
+        };
+        self.profiles.get(name).map(Some).ok_or_else(|| {
+            AppError::ConfigError(format!(
+                "Unknown profile '{}' (available: {})",
+                name,
+                if self.profiles.is_empty() {
+                    "none".to_string()
+                } else {
+                    self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                }
+            ))
+        })
+    }
+}
+
+/// A named set of options that is switched on as a whole with `--profile`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(flatten)]
+    pub run: RunConfig,
+    #[serde(flatten)]
+    pub selection: SelectionConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_size: Option<usize>,
+    /// Glob patterns selecting the tasks to run, matched against the task name
+    /// or, for unnamed tasks, against its data paths and rubric
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tasks: Vec<String>,
+}
+
+impl Profile {
+    pub fn selects(&self, task_config: &TaskConfig) -> Result<bool, AppError> {
+        if self.tasks.is_empty() {
+            return Ok(true);
+        }
+
+        let candidates: Vec<&str> = match &task_config.name {
+            Some(name) => vec![name.as_str()],
+            None => task_config
+                .data
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::once(task_config.rubric_template.as_str()))
+                .collect(),
+        };
+
+        for filter in &self.tasks {
+            let pattern = glob::Pattern::new(filter).map_err(|e| {
+                AppError::ConfigError(format!("Invalid task filter '{}': {}", filter, e))
+            })?;
+            if candidates.iter().any(|candidate| pattern.matches(candidate)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tasks: vec![],
+            llamafile_url: default_llamafile_url(),
+            cache_dir: default_cache_dir(),
+            rubrics_dir: default_rubrics_dir(),
+            data_dir: default_data_dir(),
+            lock_url: None,
+            data_url: default_data_url(),
+            rubric_url: default_rubric_url(),
+            hf_endpoint: None,
+            proxy: None,
+            ca_bundle: None,
+            results_db: None,
+            backup: BackupMode::None,
+            run: RunConfig::default(),
+            selection: SelectionConfig::default(),
+            profiles: IndexMap::new(),
+            profile: None,
+            noninteractive: false,
+            offline: false,
+            no_cache: false,
+            refresh: false,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupMode {
+    /// Do not keep a copy of the original dataset
+    #[default]
+    None,
+    /// Keep the original dataset as `<file>.bak`
+    Bak,
+    /// Keep a `<file>.<timestamp>.bak` copy on every run
+    Timestamped,
 }
 
 pub fn default_llamafile_url() -> String {
@@ -120,18 +289,336 @@ pub fn default_data_dir() -> String {
     DATA_DIR.to_string()
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+pub fn default_data_url() -> String {
+    DATA_URL.to_string()
+}
+
+pub fn default_rubric_url() -> String {
+    RUBRIC_URL.to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaskConfig {
-    pub data: String,
+    /// Optional name that profiles can select the task by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// One or more data files or glob patterns, all judged with the same rubric
+    #[serde(deserialize_with = "one_or_many")]
+    pub data: Vec<String>,
     pub rubric_template: String,
+    /// Where to write the judged dataset instead of updating `data` in place.
+    /// Treated as a directory when the task has more than one data file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Task-specific sampling and llamafile options
+    #[serde(flatten)]
+    pub run: RunConfig,
+    /// Task-specific item selection
+    #[serde(flatten)]
+    pub selection: SelectionConfig,
+}
+
+impl TaskConfig {
+    /// Expands the task's data entries into the list of files to process.
+    pub fn data_files(&self) -> Result<Vec<String>, AppError> {
+        let mut files: Vec<String> = Vec::new();
+
+        for entry in &self.data {
+            if !entry.contains(['*', '?', '[']) {
+                files.push(entry.clone());
+                continue;
+            }
+
+            let mut matches = glob::glob(entry)
+                .map_err(|e| {
+                    AppError::ConfigError(format!("Invalid data glob '{}': {}", entry, e))
+                })?
+                .filter_map(Result::ok)
+                .filter(|path| path.is_file())
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+
+            if matches.is_empty() {
+                return Err(AppError::ConfigError(format!(
+                    "Data glob '{}' did not match any files",
+                    entry
+                )));
+            }
+            matches.sort();
+            files.extend(matches);
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        files.retain(|file| seen.insert(file.clone()));
+
+        if files.is_empty() {
+            return Err(AppError::ConfigError(
+                "Task has no data files".to_string(),
+            ));
+        }
+        Ok(files)
+    }
+}
+
+/// Sampling and llamafile options.
+///
+/// Every field is optional so the same options can be set on the config, on a
+/// task and on the command line; the most specific value that is set wins.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunConfig {
+    /// Path to a llamafile used instead of the downloaded Flow-Judge model
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub context_size: Option<usize>,
+    #[serde(default)]
+    pub gpu_layers: Option<usize>,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub thread_count: Option<usize>,
+    #[serde(default)]
+    pub llamafile_kvargs: Option<String>,
+    #[serde(default)]
+    pub disable_kv_offload: Option<bool>,
+    /// Sampling seed, for reproducible judgments
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Seconds an item may take before its llamafile is killed
+    #[serde(default)]
+    pub timeout: Option<f64>,
+}
+
+impl RunConfig {
+    /// The built-in default of every option.
+    pub fn defaults() -> RunConfig {
+        RunConfig {
+            model: None,
+            context_size: Some(DEFAULT_CONTEXT_SIZE),
+            gpu_layers: Some(DEFAULT_GPU_LAYERS),
+            temperature: Some(DEFAULT_TEMPERATURE),
+            max_tokens: Some(DEFAULT_MAX_TOKENS),
+            thread_count: None,
+            llamafile_kvargs: None,
+            disable_kv_offload: Some(false),
+            seed: None,
+            timeout: None,
+        }
+    }
+
+    /// Fills every option that is not set here from `fallback`.
+    pub fn or(&self, fallback: &RunConfig) -> RunConfig {
+        RunConfig {
+            model: self.model.clone().or_else(|| fallback.model.clone()),
+            context_size: self.context_size.or(fallback.context_size),
+            gpu_layers: self.gpu_layers.or(fallback.gpu_layers),
+            temperature: self.temperature.or(fallback.temperature),
+            max_tokens: self.max_tokens.or(fallback.max_tokens),
+            thread_count: self.thread_count.or(fallback.thread_count),
+            llamafile_kvargs: self
+                .llamafile_kvargs
+                .clone()
+                .or_else(|| fallback.llamafile_kvargs.clone()),
+            disable_kv_offload: self.disable_kv_offload.or(fallback.disable_kv_offload),
+            seed: self.seed.or(fallback.seed),
+            timeout: self.timeout.or(fallback.timeout),
+        }
+    }
+
+    /// Applies the built-in defaults to every option that is still unset.
+    pub fn resolve(&self) -> RunParams {
+        RunParams {
+            model: self.model.clone(),
+            context_size: self.context_size.unwrap_or(DEFAULT_CONTEXT_SIZE),
+            gpu_layers: self.gpu_layers.unwrap_or(DEFAULT_GPU_LAYERS),
+            temperature: self.temperature.unwrap_or(DEFAULT_TEMPERATURE),
+            max_tokens: self.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            thread_count: self.thread_count,
+            llamafile_kvargs: self.llamafile_kvargs.clone(),
+            disable_kv_offload: self.disable_kv_offload.unwrap_or(false),
+            seed: self.seed,
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// Selects the subset of a dataset to judge. Judgments of the subset are
+/// merged back into the full file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SelectionConfig {
+    /// Judge only the first N (selected) items
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// Judge a random sample of N items
+    #[serde(default)]
+    pub sample: Option<usize>,
+    /// Seed of the random sample, so it can be reproduced
+    #[serde(default)]
+    pub sample_seed: Option<u64>,
+    /// Field to stratify the random sample on
+    #[serde(default)]
+    pub stratify_by: Option<String>,
+    /// Field filter such as `category == billing && score ==`
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Shard `i/n` (1-based) of the dataset, by item position
+    #[serde(default)]
+    pub shard: Option<String>,
+}
+
+impl SelectionConfig {
+    /// Fills every option that is not set here from `fallback`.
+    pub fn or(&self, fallback: &SelectionConfig) -> SelectionConfig {
+        SelectionConfig {
+            limit: self.limit.or(fallback.limit),
+            sample: self.sample.or(fallback.sample),
+            sample_seed: self.sample_seed.or(fallback.sample_seed),
+            stratify_by: self.stratify_by.clone().or_else(|| fallback.stratify_by.clone()),
+            filter: self.filter.clone().or_else(|| fallback.filter.clone()),
+            shard: self.shard.clone().or_else(|| fallback.shard.clone()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == SelectionConfig::default()
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Fully resolved options for running the llamafile on one task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunParams {
+    pub model: Option<String>,
+    pub context_size: usize,
+    pub gpu_layers: usize,
+    pub temperature: f64,
+    pub max_tokens: usize,
+    pub thread_count: Option<usize>,
+    pub llamafile_kvargs: Option<String>,
+    pub disable_kv_offload: bool,
+    pub seed: Option<u64>,
+    pub timeout: Option<f64>,
+}
+
+/// Accepts a boolean, or a number or word as environment variables set it
+/// (`1`, `yes`, `on`, `0`, `no`, `off`).
+fn flag<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Null => Ok(false),
+        Value::Bool(value) => Ok(value),
+        Value::Number(n) => Ok(n.as_f64() != Some(0.0)),
+        Value::String(s) => match s.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "y" | "on" => Ok(true),
+            "" | "0" | "false" | "no" | "n" | "off" => Ok(false),
+            other => Err(serde::de::Error::custom(format!(
+                "expected a boolean, got '{}'",
+                other
+            ))),
+        },
+        other => Err(serde::de::Error::custom(format!(
+            "expected a boolean, got {}",
+            other
+        ))),
+    }
+}
+
+/// Accepts either a single string or a list of strings.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+/// A single dataset record.
+///
+/// Every field of the source record is kept in its original order, so writing
+/// the dataset back only adds or updates the `feedback` and `score` columns.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct IoItem {
-    pub input: String,
-    pub output: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub feedback: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub score: Option<i32>,
+    pub fields: IndexMap<String, Value>,
+}
+
+impl IoItem {
+    pub fn input(&self) -> Option<&str> {
+        self.fields.get("input").and_then(Value::as_str)
+    }
+
+    pub fn output(&self) -> Option<&str> {
+        self.fields.get("output").and_then(Value::as_str)
+    }
+
+    pub fn feedback(&self) -> Option<&str> {
+        self.fields
+            .get("feedback")
+            .and_then(Value::as_str)
+            .filter(|f| !f.is_empty())
+    }
+
+    /// Returns the score, accepting both JSON numbers and numeric CSV cells.
+    pub fn score(&self) -> Option<i32> {
+        self.int_field("score")
+    }
+
+    /// Returns an integer field such as a human label, accepting both JSON
+    /// numbers and numeric CSV cells.
+    pub fn int_field(&self, field: &str) -> Option<i32> {
+        match self.fields.get(field)? {
+            Value::Number(n) => n.as_i64().and_then(|n| i32::try_from(n).ok()),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Sets the feedback, removing the field for `None` so unjudged items
+    /// are written back without it.
+    pub fn set_feedback(&mut self, feedback: Option<String>) {
+        self.set_field("feedback", feedback.map(Value::String));
+    }
+
+    /// Sets the score, removing the field for `None`.
+    pub fn set_score(&mut self, score: Option<i32>) {
+        self.set_field("score", score.map(Value::from));
+    }
+
+    /// Whether judging the item last timed out; its earlier judgment, if
+    /// any, is kept.
+    pub fn timed_out(&self) -> bool {
+        match self.fields.get("timed_out") {
+            Some(Value::Bool(timed_out)) => *timed_out,
+            Some(Value::String(cell)) => cell == "true",
+            _ => false,
+        }
+    }
+
+    /// Marks the item as timed out, removing the mark once it is judged.
+    pub fn set_timed_out(&mut self, timed_out: bool) {
+        self.set_field("timed_out", timed_out.then_some(Value::Bool(true)));
+    }
+
+    fn set_field(&mut self, field: &str, value: Option<Value>) {
+        match value {
+            Some(value) => {
+                self.fields.insert(field.to_string(), value);
+            }
+            None => {
+                self.fields.shift_remove(field);
+            }
+        }
+    }
 }
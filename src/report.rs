@@ -0,0 +1,388 @@
+use crate::cli::ReportFormat;
+use crate::models::{AppError, IoItem};
+use crate::selection::field_text;
+use crate::stats::{bootstrap_ci, mean, median};
+use console::{measure_text_width, style};
+use indexmap::IndexMap;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Options of `fwj report`.
+#[derive(Debug, Clone)]
+pub struct ReportOptions {
+    pub by: Option<String>,
+    pub resamples: usize,
+    pub confidence: f64,
+    pub seed: u64,
+}
+
+/// A point estimate with its bootstrap confidence interval.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Estimate {
+    pub value: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+/// Aggregate statistics over a set of judged items.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub items: usize,
+    pub scored: usize,
+    /// Items the judge ran on but no score could be parsed from.
+    pub parse_failures: usize,
+    pub parse_failure_rate: f64,
+    pub not_judged: usize,
+    /// Items whose last judging timed out, whether or not an earlier
+    /// judgment is kept.
+    pub timed_out: usize,
+    pub mean: Option<Estimate>,
+    pub median: Option<Estimate>,
+    pub histogram: BTreeMap<i32, usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub file: String,
+    pub confidence: f64,
+    #[serde(flatten)]
+    pub summary: Summary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by: Option<String>,
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub groups: IndexMap<String, Summary>,
+}
+
+/// Computes the report for the items of `file`, optionally broken down by
+/// the values of a field in order of first appearance.
+pub fn build(file: &str, items: &[IoItem], options: &ReportOptions) -> Report {
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let all: Vec<&IoItem> = items.iter().collect();
+    let summary = summarize(&all, options, &mut rng);
+
+    let mut groups: IndexMap<String, Vec<&IoItem>> = IndexMap::new();
+    if let Some(field) = &options.by {
+        for item in items {
+            let key = field_text(item, field)
+                .filter(|value| !value.is_empty())
+                .unwrap_or_else(|| "(none)".to_string());
+            groups.entry(key).or_default().push(item);
+        }
+    }
+
+    Report {
+        file: file.to_string(),
+        confidence: options.confidence,
+        summary,
+        by: options.by.clone(),
+        groups: groups
+            .into_iter()
+            .map(|(key, items)| (key, summarize(&items, options, &mut rng)))
+            .collect(),
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn summarize(items: &[&IoItem], options: &ReportOptions, rng: &mut StdRng) -> Summary {
+    let mut scores = Vec::new();
+    let mut histogram = BTreeMap::new();
+    let mut parse_failures = 0;
+    for item in items {
+        match item.score() {
+            Some(score) => {
+                scores.push(f64::from(score));
+                *histogram.entry(score).or_insert(0) += 1;
+            }
+            None if item.feedback().is_some() => parse_failures += 1,
+            None => {}
+        }
+    }
+
+    let estimate = |statistic: fn(&[f64]) -> f64, rng: &mut StdRng| {
+        if scores.is_empty() {
+            return None;
+        }
+        let (low, high) = bootstrap_ci(
+            &scores,
+            statistic,
+            options.resamples,
+            options.confidence,
+            rng,
+        );
+        Some(Estimate {
+            value: statistic(&scores),
+            low,
+            high,
+        })
+    };
+
+    let attempted = scores.len() + parse_failures;
+    Summary {
+        items: items.len(),
+        scored: scores.len(),
+        parse_failures,
+        parse_failure_rate: if attempted == 0 {
+            0.0
+        } else {
+            parse_failures as f64 / attempted as f64
+        },
+        not_judged: items.len() - attempted,
+        timed_out: items.iter().filter(|item| item.timed_out()).count(),
+        mean: estimate(mean, rng),
+        median: estimate(median, rng),
+        histogram,
+    }
+}
+
+/// Runs `fwj report`: reads a judged JSON or CSV file and prints its statistics.
+pub fn run(file: &str, format: ReportFormat, options: &ReportOptions) -> Result<(), AppError> {
+    if !(options.confidence > 0.0 && options.confidence < 1.0) {
+        return Err(AppError::ConfigError(format!(
+            "Confidence must be between 0 and 1, got {}",
+            options.confidence
+        )));
+    }
+    let items = crate::read_items(file)?;
+    let report = build(file, &items, options);
+    match format {
+        ReportFormat::Table => print_table(&report),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        ReportFormat::Markdown => print!("{}", to_markdown(&report)),
+    }
+    Ok(())
+}
+
+fn format_estimate(estimate: Option<Estimate>) -> String {
+    estimate.map_or_else(
+        || "-".to_string(),
+        |e| format!("{:.2} [{:.2}, {:.2}]", e.value, e.low, e.high),
+    )
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn share(count: usize, total: usize) -> String {
+    if total == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.1}%", count as f64 * 100.0 / total as f64)
+    }
+}
+
+fn overview_rows(report: &Report) -> Vec<(String, String)> {
+    let summary = &report.summary;
+    let ci = format!("{:.0}% CI", report.confidence * 100.0);
+    vec![
+        ("Items".to_string(), summary.items.to_string()),
+        ("Scored".to_string(), summary.scored.to_string()),
+        (
+            "Parse failures".to_string(),
+            format!(
+                "{} ({:.1}%)",
+                summary.parse_failures,
+                summary.parse_failure_rate * 100.0
+            ),
+        ),
+        ("Not judged".to_string(), summary.not_judged.to_string()),
+        ("Timed out".to_string(), summary.timed_out.to_string()),
+        (format!("Mean ({})", ci), format_estimate(summary.mean)),
+        (format!("Median ({})", ci), format_estimate(summary.median)),
+    ]
+}
+
+fn group_rows(report: &Report) -> Vec<Vec<String>> {
+    report
+        .groups
+        .iter()
+        .map(|(group, summary)| {
+            vec![
+                group.clone(),
+                summary.items.to_string(),
+                summary.scored.to_string(),
+                share(
+                    summary.parse_failures,
+                    summary.scored + summary.parse_failures,
+                ),
+                format_estimate(summary.mean),
+                summary
+                    .median
+                    .map_or_else(|| "-".to_string(), |e| format!("{:.2}", e.value)),
+            ]
+        })
+        .collect()
+}
+
+const GROUP_HEADERS: [&str; 6] = ["", "Items", "Scored", "Failures", "Mean [CI]", "Median"];
+
+fn print_table(report: &Report) {
+    println!(
+        "\n{}",
+        style(format!("Report for {}", report.file)).yellow().bold()
+    );
+    let rows = overview_rows(report);
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    for (label, value) in rows {
+        println!("  {:<width$}  {}", label, value, width = width);
+    }
+
+    let histogram = &report.summary.histogram;
+    if !histogram.is_empty() {
+        println!("\n{}", style("Score distribution:").bold());
+        let most = histogram.values().copied().max().unwrap_or(1);
+        for (score, count) in histogram {
+            println!(
+                "  {:>3} │ {:<40} {} ({})",
+                score,
+                "█".repeat((count * 40).div_ceil(most)),
+                count,
+                share(*count, report.summary.scored)
+            );
+        }
+    }
+
+    if let Some(field) = &report.by {
+        println!("\n{}", style(format!("By {}:", field)).bold());
+        let mut headers = GROUP_HEADERS.map(str::to_string);
+        headers[0].clone_from(field);
+        print!("{}", render_table(&headers, &group_rows(report), false));
+    }
+}
+
+fn to_markdown(report: &Report) -> String {
+    let mut markdown = format!("## Report for `{}`\n\n", report.file);
+    let rows: Vec<Vec<String>> = overview_rows(report)
+        .into_iter()
+        .map(|(label, value)| vec![label, value])
+        .collect();
+    markdown.push_str(&render_table(
+        &["Metric".to_string(), "Value".to_string()],
+        &rows,
+        true,
+    ));
+
+    if !report.summary.histogram.is_empty() {
+        markdown.push_str("\n### Score distribution\n\n");
+        let rows: Vec<Vec<String>> = report
+            .summary
+            .histogram
+            .iter()
+            .map(|(score, count)| {
+                vec![
+                    score.to_string(),
+                    count.to_string(),
+                    share(*count, report.summary.scored),
+                ]
+            })
+            .collect();
+        markdown.push_str(&render_table(
+            &[
+                "Score".to_string(),
+                "Count".to_string(),
+                "Share".to_string(),
+            ],
+            &rows,
+            true,
+        ));
+    }
+
+    if let Some(field) = &report.by {
+        markdown.push_str("\n### By `");
+        markdown.push_str(field);
+        markdown.push_str("`\n\n");
+        let mut headers = GROUP_HEADERS.map(str::to_string);
+        headers[0].clone_from(field);
+        markdown.push_str(&render_table(&headers, &group_rows(report), true));
+    }
+    markdown
+}
+
+/// Renders rows as a box-drawn terminal table or a Markdown table. Columns
+/// holding text are left-aligned, numeric columns right-aligned.
+pub fn render_table(headers: &[String], rows: &[Vec<String>], markdown: bool) -> String {
+    // Pipes would end a Markdown cell early
+    let escaped: Vec<Vec<String>>;
+    let rows = if markdown {
+        escaped = rows
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.replace('|', "\\|")).collect())
+            .collect();
+        &escaped
+    } else {
+        rows
+    };
+
+    let widths: Vec<usize> = (0..headers.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| measure_text_width(&row[column]))
+                .chain(std::iter::once(measure_text_width(&headers[column])))
+                .chain(std::iter::once(if markdown { 3 } else { 0 }))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let left_aligned: Vec<bool> = (0..headers.len())
+        .map(|column| {
+            column == 0
+                || rows
+                    .iter()
+                    .any(|row| row[column].chars().any(char::is_alphabetic))
+        })
+        .collect();
+
+    let line = |cells: &[String], left: &str, separator: &str, right: &str| {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .zip(&left_aligned)
+            .map(|((cell, width), left_aligned)| {
+                let padding = " ".repeat(width - measure_text_width(cell));
+                if *left_aligned {
+                    format!("{}{}", cell, padding)
+                } else {
+                    format!("{}{}", padding, cell)
+                }
+            })
+            .collect();
+        format!(
+            "{} {} {}\n",
+            left,
+            cells.join(&format!(" {} ", separator)),
+            right
+        )
+    };
+    let rule = |left: &str, mid: &str, right: &str, fill: &str| {
+        let parts: Vec<String> = widths.iter().map(|width| fill.repeat(width + 2)).collect();
+        format!("{}{}{}\n", left, parts.join(mid), right)
+    };
+
+    let mut table = String::new();
+    if markdown {
+        table.push_str(&line(headers, "|", "|", "|"));
+        let separators: Vec<String> = widths
+            .iter()
+            .zip(&left_aligned)
+            .map(|(width, left_aligned)| {
+                if *left_aligned {
+                    "-".repeat(*width)
+                } else {
+                    format!("{}:", "-".repeat(width - 1))
+                }
+            })
+            .collect();
+        table.push_str(&line(&separators, "|", "|", "|"));
+        for row in rows {
+            table.push_str(&line(row, "|", "|", "|"));
+        }
+    } else {
+        table.push_str(&rule("┌", "┬", "┐", "─"));
+        table.push_str(&line(headers, "│", "│", "│"));
+        table.push_str(&rule("├", "┼", "┤", "─"));
+        for row in rows {
+            table.push_str(&line(row, "│", "│", "│"));
+        }
+        table.push_str(&rule("└", "┴", "┘", "─"));
+    }
+    table
+}
@@ -0,0 +1,126 @@
+use crate::cache::cached_sha256;
+use crate::lock::FileLock;
+use crate::models::{AppError, Config, RunParams};
+use crate::parse_score;
+use crate::store::sha256_hex;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Directory of the result cache inside the cache directory.
+pub const RESULTS_DIR: &str = "results";
+
+/// Everything that determines a judgment, hashed into its cache key.
+#[derive(Serialize)]
+struct KeyFields<'a> {
+    prompt: &'a str,
+    model_sha256: &'a str,
+    temperature: f64,
+    max_tokens: usize,
+    seed: Option<u64>,
+}
+
+/// A judgment stored in the cache.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedJudgment {
+    output: String,
+    /// When the judgment was made, in RFC 3339.
+    created_at: String,
+}
+
+/// Judgments stored by a hash of the rendered prompt, the model and the
+/// sampling parameters, so unchanged items are not judged again.
+#[derive(Debug)]
+pub struct ResultCache {
+    dir: PathBuf,
+    model_sha256: String,
+    /// Judge again and overwrite instead of reusing stored judgments.
+    refresh: bool,
+    hits: AtomicUsize,
+    /// Keeps `fwj cache clean` from removing the cache while it is in use.
+    _lock: FileLock,
+}
+
+impl ResultCache {
+    /// Opens the result cache for judging with `llamafile_path`, or `None`
+    /// with `no_cache` set. A model that cannot be hashed disables the cache.
+    pub fn open(config: &Config, llamafile_path: &Path) -> Result<Option<Self>, AppError> {
+        if config.no_cache {
+            return Ok(None);
+        }
+        let model_sha256 = match cached_sha256(llamafile_path) {
+            Ok(sha256) => sha256,
+            Err(e) => {
+                warn!("Not using the result cache: {}", e);
+                return Ok(None);
+            }
+        };
+        let dir = Path::new(&config.cache_dir).join(RESULTS_DIR);
+        std::fs::create_dir_all(&dir)?;
+        Ok(Some(Self {
+            _lock: FileLock::shared(&dir)?,
+            dir,
+            model_sha256,
+            refresh: config.refresh,
+            hits: AtomicUsize::new(0),
+        }))
+    }
+
+    /// The key of the judgment of `prompt` with the sampling parameters of `params`.
+    pub fn key(&self, prompt: &str, params: &RunParams) -> String {
+        let fields = KeyFields {
+            prompt,
+            model_sha256: &self.model_sha256,
+            temperature: params.temperature,
+            max_tokens: params.max_tokens,
+            seed: params.seed,
+        };
+        sha256_hex(&serde_json::to_vec(&fields).unwrap_or_default())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(format!("{}.json", key))
+    }
+
+    /// The stored judge output for `key`, counted as a hit.
+    pub fn get(&self, key: &str) -> Option<String> {
+        if self.refresh {
+            return None;
+        }
+        let content = std::fs::read_to_string(self.path(key)).ok()?;
+        let judgment: CachedJudgment = serde_json::from_str(&content).ok()?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(judgment.output)
+    }
+
+    /// Stores the judge output for `key`. Outputs without a score are not
+    /// stored, so those items are judged again next time; failing to store
+    /// only costs judging again.
+    pub fn put(&self, key: &str, output: &str) {
+        if parse_score(output).is_none() {
+            return;
+        }
+        let path = self.path(key);
+        let judgment = CachedJudgment {
+            output: output.to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        let write = || -> Result<(), AppError> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            crate::write_atomic(&path.to_string_lossy(), |file| {
+                serde_json::to_writer(file, &judgment).map_err(AppError::from)
+            })
+        };
+        if let Err(e) = write() {
+            debug!("Could not cache the judgment {}: {}", key, e);
+        }
+    }
+
+    /// Number of judgments reused so far.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
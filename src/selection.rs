@@ -0,0 +1,259 @@
+use crate::models::{AppError, IoItem, SelectionConfig};
+use indexmap::IndexMap;
+use log::info;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use regex::Regex;
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// Returns the positions of the items to judge, in file order.
+///
+/// The filter and shard are applied first, both on the original item
+/// positions, so shards of a dataset never overlap. The random or stratified
+/// sample and the limit are then taken from what remains.
+pub fn select(items: &[IoItem], selection: &SelectionConfig) -> Result<Vec<usize>, AppError> {
+    let filter = selection.filter.as_deref().map(Filter::parse).transpose()?;
+    let shard = selection.shard.as_deref().map(parse_shard).transpose()?;
+
+    let mut selected: Vec<usize> = items
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| shard.is_none_or(|(shard, count)| index % count == shard - 1))
+        .filter(|(_, item)| filter.as_ref().is_none_or(|filter| filter.matches(item)))
+        .map(|(index, _)| index)
+        .collect();
+
+    if let Some(sample) = selection.sample {
+        let seed = selection.sample_seed.unwrap_or_else(rand::random);
+        info!("Sampling {} items with seed {}", sample, seed);
+        let mut rng = StdRng::seed_from_u64(seed);
+        selected = match &selection.stratify_by {
+            Some(field) => stratified_sample(items, &selected, field, sample, &mut rng),
+            None => selected
+                .choose_multiple(&mut rng, sample.min(selected.len()))
+                .copied()
+                .collect(),
+        };
+        selected.sort_unstable();
+    }
+
+    if let Some(limit) = selection.limit {
+        selected.truncate(limit);
+    }
+
+    Ok(selected)
+}
+
+/// Samples `sample` items, allocating them to the groups of `field` in
+/// proportion to the group sizes (largest remainder method).
+fn stratified_sample(
+    items: &[IoItem],
+    candidates: &[usize],
+    field: &str,
+    sample: usize,
+    rng: &mut StdRng,
+) -> Vec<usize> {
+    let mut groups: IndexMap<String, Vec<usize>> = IndexMap::new();
+    for &index in candidates {
+        let key = field_text(&items[index], field).unwrap_or_default();
+        groups.entry(key).or_default().push(index);
+    }
+
+    let total = candidates.len();
+    let sample = sample.min(total);
+    if total == 0 {
+        return Vec::new();
+    }
+
+    let mut allocations: Vec<(usize, usize, usize)> = groups
+        .values()
+        .enumerate()
+        .map(|(group, indices)| {
+            let exact = indices.len() * sample;
+            (group, exact / total, exact % total)
+        })
+        .collect();
+
+    let allocated: usize = allocations.iter().map(|(_, count, _)| count).sum();
+    allocations.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+    for allocation in allocations.iter_mut().take(sample - allocated) {
+        allocation.1 += 1;
+    }
+
+    let mut selected = Vec::with_capacity(sample);
+    for (group, count, _) in allocations {
+        let indices = &groups[group];
+        selected.extend(indices.choose_multiple(rng, count).copied());
+    }
+    selected
+}
+
+fn parse_shard(shard: &str) -> Result<(usize, usize), AppError> {
+    let invalid = || {
+        AppError::ConfigError(format!(
+            "Invalid shard '{}': expected i/n with 1 <= i <= n",
+            shard
+        ))
+    };
+    let (index, count) = shard.split_once('/').ok_or_else(invalid)?;
+    let index: usize = index.trim().parse().map_err(|_| invalid())?;
+    let count: usize = count.trim().parse().map_err(|_| invalid())?;
+    if index == 0 || index > count {
+        return Err(invalid());
+    }
+    Ok((index, count))
+}
+
+/// The value of a field as text: strings as they are, missing and null
+/// values as empty, anything else as JSON.
+pub fn field_text(item: &IoItem, field: &str) -> Option<String> {
+    match item.fields.get(field)? {
+        Value::Null => Some(String::new()),
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+#[derive(Debug)]
+enum Op {
+    Eq,
+    Ne,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+    Matches(Regex),
+}
+
+#[derive(Debug)]
+struct Condition {
+    field: String,
+    op: Op,
+    value: String,
+}
+
+/// A filter expression: conditions `field OP value` joined with `&&`.
+///
+/// Operators are `==`, `!=`, `>=`, `<=`, `>`, `<` and `~` (regex match).
+/// Values may be quoted, and must be to contain `&&`; comparisons are numeric
+/// when both sides are numbers.
+/// An empty value matches missing, null and empty fields, so `score == ''`
+/// selects the items that have not been judged yet.
+#[derive(Debug)]
+pub struct Filter {
+    conditions: Vec<Condition>,
+}
+
+impl Filter {
+    pub fn parse(expression: &str) -> Result<Self, AppError> {
+        const OPERATORS: [&str; 7] = ["==", "!=", ">=", "<=", "~", ">", "<"];
+
+        let mut conditions = Vec::new();
+        for part in split_conditions(expression)? {
+            let part = part.trim();
+            let (position, operator) = OPERATORS
+                .iter()
+                .filter_map(|op| part.find(op).map(|position| (position, *op)))
+                .min_by_key(|(position, op)| (*position, std::cmp::Reverse(op.len())))
+                .ok_or_else(|| {
+                    AppError::ConfigError(format!(
+                        "Invalid filter condition '{}': expected field OP value",
+                        part
+                    ))
+                })?;
+
+            let field = part[..position].trim();
+            if field.is_empty() {
+                return Err(AppError::ConfigError(format!(
+                    "Invalid filter condition '{}': missing field name",
+                    part
+                )));
+            }
+            let value = unquote(part[position + operator.len()..].trim()).to_string();
+
+            let op = match operator {
+                "==" => Op::Eq,
+                "!=" => Op::Ne,
+                ">=" => Op::Ge,
+                "<=" => Op::Le,
+                ">" => Op::Gt,
+                "<" => Op::Lt,
+                _ => Op::Matches(Regex::new(&value)?),
+            };
+            conditions.push(Condition {
+                field: field.to_string(),
+                op,
+                value,
+            });
+        }
+
+        Ok(Filter { conditions })
+    }
+
+    pub fn matches(&self, item: &IoItem) -> bool {
+        self.conditions.iter().all(|condition| {
+            let actual = field_text(item, &condition.field).unwrap_or_default();
+            match &condition.op {
+                Op::Eq => compare(&actual, &condition.value) == Ordering::Equal,
+                Op::Ne => compare(&actual, &condition.value) != Ordering::Equal,
+                Op::Ge => !actual.is_empty() && compare(&actual, &condition.value).is_ge(),
+                Op::Le => !actual.is_empty() && compare(&actual, &condition.value).is_le(),
+                Op::Gt => !actual.is_empty() && compare(&actual, &condition.value).is_gt(),
+                Op::Lt => !actual.is_empty() && compare(&actual, &condition.value).is_lt(),
+                Op::Matches(regex) => regex.is_match(&actual),
+            }
+        })
+    }
+}
+
+/// Splits a filter expression at each `&&` outside of a quoted value.
+fn split_conditions(expression: &str) -> Result<Vec<&str>, AppError> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut chars = expression.char_indices();
+    while let Some((position, c)) = chars.next() {
+        if let Some(open) = quote {
+            if c == open {
+                quote = None;
+            }
+        } else if (c == '"' || c == '\'')
+            && expression[start..position]
+                .trim_end()
+                .ends_with(['=', '<', '>', '~'])
+        {
+            // Only a quote right after the operator starts a quoted value
+            quote = Some(c);
+        } else if expression[position..].starts_with("&&") {
+            parts.push(&expression[start..position]);
+            chars.next();
+            start = position + 2;
+        }
+    }
+    if quote.is_some() {
+        return Err(AppError::ConfigError(format!(
+            "Invalid filter '{}': unterminated quoted value",
+            expression
+        )));
+    }
+    parts.push(&expression[start..]);
+    Ok(parts)
+}
+
+fn compare(actual: &str, expected: &str) -> Ordering {
+    match (actual.trim().parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(actual), Ok(expected)) => actual.partial_cmp(&expected).unwrap_or(Ordering::Equal),
+        _ => actual.cmp(expected),
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
@@ -0,0 +1,98 @@
+use crate::models::AppError;
+use console::style;
+use log::debug;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+
+/// Exit code of a process ended by SIGINT.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// How far a shutdown has progressed; each signal moves it one step.
+const RUNNING: u8 = 0;
+const STOPPING: u8 = 1;
+const CANCELLING: u8 = 2;
+
+/// Shared view of a requested shutdown.
+///
+/// The first request stops scheduling new items while the ones in flight
+/// finish, the second cancels those too, and the third exits right away.
+/// Judgments completed before the shutdown are still written.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: AtomicU8,
+    cancelled: Notify,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the next step of the shutdown, as SIGINT and SIGTERM do.
+    pub fn request(&self) {
+        match self.inner.state.fetch_add(1, Ordering::SeqCst) {
+            RUNNING => println!(
+                "\n{}",
+                style(
+                    "Stopping: finishing the items in progress (press Ctrl-C again to cancel them)"
+                )
+                .yellow()
+            ),
+            STOPPING => {
+                println!(
+                    "\n{}",
+                    style("Cancelling the items in progress and writing completed judgments")
+                        .yellow()
+                );
+                self.inner.cancelled.notify_waiters();
+            }
+            _ => std::process::exit(INTERRUPTED_EXIT_CODE),
+        }
+    }
+
+    /// Handles SIGINT and SIGTERM by requesting a shutdown, instead of
+    /// ending the process.
+    pub fn listen(&self) -> Result<(), AppError> {
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = interrupt.recv() => debug!("Received SIGINT"),
+                    _ = terminate.recv() => debug!("Received SIGTERM"),
+                }
+                shutdown.request();
+            }
+        });
+        Ok(())
+    }
+
+    /// Whether new items should no longer be started.
+    pub fn is_stopping(&self) -> bool {
+        self.inner.state.load(Ordering::SeqCst) >= STOPPING
+    }
+
+    fn is_cancelling(&self) -> bool {
+        self.inner.state.load(Ordering::SeqCst) >= CANCELLING
+    }
+
+    /// Completes once the items in progress are to be cancelled.
+    pub async fn cancelled(&self) {
+        let notified = self.inner.cancelled.notified();
+        tokio::pin!(notified);
+        // Registered before checking, so a request in between is not missed
+        notified.as_mut().enable();
+        if self.is_cancelling() {
+            return;
+        }
+        notified.await;
+    }
+}
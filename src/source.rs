@@ -0,0 +1,134 @@
+use crate::models::{AppError, Config};
+use reqwest::{Certificate, Client, Proxy};
+use std::fmt;
+use std::path::PathBuf;
+
+/// Prefix of the default model sources, replaced by a configured mirror.
+const HF_HOST: &str = "https://huggingface.co";
+/// Variable the Hugging Face tools read the mirror from.
+const HF_ENDPOINT_VAR: &str = "HF_ENDPOINT";
+
+/// Where an artifact is fetched from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Http(String),
+    File(PathBuf),
+}
+
+impl Source {
+    /// Parses an `http://` or `https://` URL, a `file://` URL or a plain path.
+    pub fn parse(location: &str) -> Self {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            Source::Http(location.to_string())
+        } else {
+            let path = location.strip_prefix("file://").unwrap_or(location);
+            Source::File(PathBuf::from(path))
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Http(url) => write!(f, "{}", url),
+            Source::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// The Hugging Face mirror: `hf_endpoint` from the config, else `HF_ENDPOINT`.
+pub fn hf_endpoint(config: &Config) -> Option<String> {
+    config
+        .hf_endpoint
+        .clone()
+        .or_else(|| std::env::var(HF_ENDPOINT_VAR).ok())
+        .map(|endpoint| endpoint.trim_end_matches('/').to_string())
+        .filter(|endpoint| !endpoint.is_empty())
+}
+
+/// Resolves a configured location, pointing Hugging Face URLs at the mirror
+/// when one is configured.
+pub fn resolve(config: &Config, location: &str) -> Source {
+    let rest = location
+        .strip_prefix(HF_HOST)
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'));
+    match (hf_endpoint(config), rest) {
+        (Some(endpoint), Some(rest)) => Source::parse(&format!("{}{}", endpoint, rest)),
+        _ => Source::parse(location),
+    }
+}
+
+/// Where the Flow-Judge llamafile is downloaded from.
+pub fn llamafile_source(config: &Config) -> Source {
+    resolve(config, &config.llamafile_url)
+}
+
+/// Where the lock file of the llamafile comes from: `lock_url` when set,
+/// the Git LFS pointer of a Hugging Face download, or else `<llamafile_url>.lock`.
+pub fn lock_source(config: &Config) -> Source {
+    let location = config.lock_url.clone().unwrap_or_else(|| {
+        if config.llamafile_url.contains("/resolve/") {
+            config.llamafile_url.replacen("/resolve/", "/raw/", 1)
+        } else {
+            format!("{}.lock", config.llamafile_url)
+        }
+    });
+    resolve(config, &location)
+}
+
+/// The HTTP client for all downloads, using the configured proxy and CA
+/// bundle. Without a proxy in the config, the usual `HTTPS_PROXY`,
+/// `HTTP_PROXY` and `NO_PROXY` variables apply.
+pub fn http_client(config: &Config) -> Result<Client, AppError> {
+    let mut builder = Client::builder();
+    if let Some(proxy) = &config.proxy {
+        let proxy = Proxy::all(proxy)
+            .map_err(|e| AppError::ConfigError(format!("Invalid proxy '{}': {}", proxy, e)))?;
+        builder = builder.proxy(proxy);
+    }
+    if let Some(bundle) = &config.ca_bundle {
+        let pem = std::fs::read(bundle).map_err(|e| {
+            AppError::ConfigError(format!("Failed to read CA bundle '{}': {}", bundle, e))
+        })?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| AppError::ConfigError(format!("Invalid CA bundle '{}': {}", bundle, e)))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// Reads a text source, such as a lock file.
+pub async fn read_text(client: &Client, source: &Source) -> Result<String, AppError> {
+    match source {
+        Source::Http(url) => Ok(client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?),
+        Source::File(path) => tokio::fs::read_to_string(path).await.map_err(|e| {
+            AppError::FileReadError(format!("Failed to read '{}': {}", path.display(), e))
+        }),
+    }
+}
+
+/// Where the `fetch` placeholders of tasks are downloaded from.
+#[derive(Debug)]
+pub struct FetchSources {
+    pub client: Client,
+    pub data: Source,
+    pub rubric: Source,
+}
+
+impl FetchSources {
+    pub fn new(config: &Config) -> Result<Self, AppError> {
+        Ok(Self {
+            client: http_client(config)?,
+            data: resolve(config, &config.data_url),
+            rubric: resolve(config, &config.rubric_url),
+        })
+    }
+}
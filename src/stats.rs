@@ -0,0 +1,254 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::cmp::Ordering;
+
+/// Arithmetic mean; `NaN` for an empty slice.
+#[allow(clippy::cast_precision_loss)]
+pub fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Median; `NaN` for an empty slice.
+pub fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    quantile(&sorted, 0.5)
+}
+
+/// Quantile of already sorted values, interpolating between neighbours.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+pub fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let position = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+}
+
+/// Percentile bootstrap confidence interval of `statistic` over `values`.
+pub fn bootstrap_ci<F>(
+    values: &[f64],
+    statistic: F,
+    resamples: usize,
+    confidence: f64,
+    rng: &mut StdRng,
+) -> (f64, f64)
+where
+    F: Fn(&[f64]) -> f64,
+{
+    if values.is_empty() || resamples == 0 {
+        return (f64::NAN, f64::NAN);
+    }
+
+    let mut sample = vec![0.0; values.len()];
+    let mut estimates: Vec<f64> = (0..resamples)
+        .map(|_| {
+            for slot in &mut sample {
+                *slot = values[rng.gen_range(0..values.len())];
+            }
+            statistic(&sample)
+        })
+        .collect();
+    estimates.sort_by(f64::total_cmp);
+
+    let alpha = (1.0 - confidence) / 2.0;
+    (
+        quantile(&estimates, alpha),
+        quantile(&estimates, 1.0 - alpha),
+    )
+}
+
+/// Ranks of `values` starting at 1, with tied values sharing their average rank.
+#[allow(clippy::cast_precision_loss)]
+pub fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]].total_cmp(&values[order[start]]).is_eq() {
+            end += 1;
+        }
+        // Positions start..end hold ranks start+1..=end
+        let rank = (start + 1 + end) as f64 / 2.0;
+        for &index in &order[start..end] {
+            ranks[index] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
+
+/// Standard normal cumulative distribution function.
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / std::f64::consts::SQRT_2)
+}
+
+/// Complementary error function, with a fractional error below 1.2e-7.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t
+        * (-z * z - 1.265_512_23
+            + t * (1.000_023_68
+                + t * (0.374_091_96
+                    + t * (0.096_784_18
+                        + t * (-0.186_288_06
+                            + t * (0.278_868_07
+                                + t * (-1.135_203_98
+                                    + t * (1.488_515_87
+                                        + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+            .exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+/// Result of a two-sided significance test using the normal approximation.
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct TestResult {
+    pub statistic: f64,
+    pub z: f64,
+    pub p_value: f64,
+    pub n: usize,
+}
+
+/// Wilcoxon signed-rank test of paired differences against zero.
+///
+/// Zero differences are dropped; the normal approximation uses a tie and
+/// continuity correction. Returns `None` when no difference is non-zero.
+#[allow(clippy::cast_precision_loss)]
+pub fn wilcoxon_signed_rank(differences: &[f64]) -> Option<TestResult> {
+    let non_zero: Vec<f64> = differences.iter().copied().filter(|d| *d != 0.0).collect();
+    if non_zero.is_empty() {
+        return None;
+    }
+
+    let magnitudes: Vec<f64> = non_zero.iter().map(|d| d.abs()).collect();
+    let ranks = ranks(&magnitudes);
+    let w_plus: f64 = non_zero
+        .iter()
+        .zip(&ranks)
+        .filter(|(d, _)| **d > 0.0)
+        .map(|(_, rank)| rank)
+        .sum();
+
+    let n = non_zero.len() as f64;
+    let expected = n * (n + 1.0) / 4.0;
+    let mut tie_correction = 0.0;
+    let mut sorted = magnitudes.clone();
+    sorted.sort_by(f64::total_cmp);
+    for group in sorted.chunk_by(|a, b| a.total_cmp(b).is_eq()) {
+        let t = group.len() as f64;
+        tie_correction += t * t * t - t;
+    }
+    let variance = n * (n + 1.0) * (2.0 * n + 1.0) / 24.0 - tie_correction / 48.0;
+
+    let deviation = w_plus - expected;
+    let z = if variance > 0.0 {
+        (deviation.abs() - 0.5).max(0.0).copysign(deviation) / variance.sqrt()
+    } else {
+        0.0
+    };
+    Some(TestResult {
+        statistic: w_plus,
+        z,
+        p_value: (2.0 * (1.0 - normal_cdf(z.abs()))).min(1.0),
+        n: non_zero.len(),
+    })
+}
+
+/// Pearson correlation; `None` when either side has no variance.
+pub fn pearson(x: &[f64], y: &[f64]) -> Option<f64> {
+    let (mean_x, mean_y) = (mean(x), mean(y));
+    let mut covariance = 0.0;
+    let mut variance_x = 0.0;
+    let mut variance_y = 0.0;
+    for (a, b) in x.iter().zip(y) {
+        covariance += (a - mean_x) * (b - mean_y);
+        variance_x += (a - mean_x).powi(2);
+        variance_y += (b - mean_y).powi(2);
+    }
+    (variance_x > 0.0 && variance_y > 0.0).then(|| covariance / (variance_x * variance_y).sqrt())
+}
+
+/// Spearman rank correlation, i.e. the Pearson correlation of the ranks.
+pub fn spearman(x: &[f64], y: &[f64]) -> Option<f64> {
+    pearson(&ranks(x), &ranks(y))
+}
+
+/// Kendall's tau-b, which accounts for ties on either side.
+#[allow(clippy::cast_precision_loss)]
+pub fn kendall_tau_b(x: &[f64], y: &[f64]) -> Option<f64> {
+    let mut concordant = 0i64;
+    let mut discordant = 0i64;
+    let mut ties_x = 0i64;
+    let mut ties_y = 0i64;
+    for i in 0..x.len() {
+        for j in i + 1..x.len() {
+            let dx = x[i].total_cmp(&x[j]);
+            let dy = y[i].total_cmp(&y[j]);
+            match (dx, dy) {
+                (Ordering::Equal, Ordering::Equal) => {}
+                (Ordering::Equal, _) => ties_x += 1,
+                (_, Ordering::Equal) => ties_y += 1,
+                _ if dx == dy => concordant += 1,
+                _ => discordant += 1,
+            }
+        }
+    }
+    let untied_x = (concordant + discordant + ties_y) as f64;
+    let untied_y = (concordant + discordant + ties_x) as f64;
+    (untied_x > 0.0 && untied_y > 0.0)
+        .then(|| (concordant - discordant) as f64 / (untied_x * untied_y).sqrt())
+}
+
+/// Cohen's kappa between two raters over `categories`, unweighted or with
+/// quadratic weights on the numeric distance between categories. `None`
+/// when the agreement expected by chance is already perfect.
+#[allow(clippy::cast_precision_loss)]
+pub fn cohen_kappa(pairs: &[(i32, i32)], categories: &[i32], quadratic: bool) -> Option<f64> {
+    let k = categories.len();
+    let index = |value: i32| categories.iter().position(|c| *c == value);
+    let mut observed = vec![vec![0.0; k]; k];
+    for (a, b) in pairs {
+        if let (Some(i), Some(j)) = (index(*a), index(*b)) {
+            observed[i][j] += 1.0;
+        }
+    }
+
+    let total = pairs.len() as f64;
+    let row_totals: Vec<f64> = observed.iter().map(|row| row.iter().sum()).collect();
+    let column_totals: Vec<f64> = (0..k)
+        .map(|j| observed.iter().map(|row| row[j]).sum())
+        .collect();
+
+    let span = match (categories.iter().min(), categories.iter().max()) {
+        (Some(min), Some(max)) if max > min => f64::from(max - min),
+        _ => 1.0,
+    };
+    let mut observed_disagreement = 0.0;
+    let mut expected_disagreement = 0.0;
+    for i in 0..k {
+        for j in 0..k {
+            let weight = if quadratic {
+                (f64::from(categories[i] - categories[j]) / span).powi(2)
+            } else {
+                f64::from(u8::from(i != j))
+            };
+            observed_disagreement += weight * observed[i][j];
+            expected_disagreement += weight * row_totals[i] * column_totals[j] / total;
+        }
+    }
+    (expected_disagreement > 0.0).then(|| 1.0 - observed_disagreement / expected_disagreement)
+}
@@ -0,0 +1,185 @@
+use crate::models::AppError;
+use rusqlite::{params, Connection};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id               INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at       TEXT NOT NULL,
+    finished_at      TEXT,
+    data_path        TEXT NOT NULL,
+    rubric_path      TEXT NOT NULL,
+    rubric_hash      TEXT NOT NULL,
+    model_id         TEXT NOT NULL,
+    temperature      REAL NOT NULL,
+    max_tokens       INTEGER NOT NULL,
+    context_size     INTEGER NOT NULL,
+    gpu_layers       INTEGER NOT NULL,
+    thread_count     INTEGER,
+    llamafile_kvargs TEXT,
+    item_count       INTEGER NOT NULL,
+    failures         INTEGER,
+    seed             TEXT,
+    timeout          REAL
+);
+
+CREATE TABLE IF NOT EXISTS items (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id     INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    item_index INTEGER NOT NULL,
+    input      TEXT NOT NULL,
+    output     TEXT NOT NULL,
+    fields     TEXT NOT NULL,
+    UNIQUE (run_id, item_index)
+);
+
+CREATE TABLE IF NOT EXISTS judgments (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    item_id    INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+    raw_output TEXT,
+    feedback   TEXT,
+    score      INTEGER,
+    latency_ms INTEGER NOT NULL,
+    attempts   INTEGER NOT NULL,
+    error      TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_items_run ON items(run_id);
+CREATE INDEX IF NOT EXISTS idx_judgments_item ON judgments(item_id);
+";
+
+/// Metadata describing one task run, stored in the `runs` table.
+#[derive(Debug)]
+pub struct RunRecord {
+    pub data_path: String,
+    pub rubric_path: String,
+    pub rubric_hash: String,
+    pub model_id: String,
+    pub temperature: f64,
+    pub max_tokens: usize,
+    pub context_size: usize,
+    pub gpu_layers: usize,
+    pub thread_count: Option<usize>,
+    pub llamafile_kvargs: Option<String>,
+    pub item_count: usize,
+    pub seed: Option<u64>,
+    pub timeout: Option<f64>,
+}
+
+/// The outcome of judging one item, stored in the `items` and `judgments` tables.
+#[derive(Debug)]
+pub struct JudgmentRecord {
+    pub item_index: usize,
+    pub input: String,
+    pub output: String,
+    pub fields: String,
+    pub raw_output: Option<String>,
+    pub feedback: Option<String>,
+    pub score: Option<i32>,
+    pub latency_ms: u128,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+/// `SQLite` results backend, written alongside the JSON/CSV dataset.
+pub struct ResultsStore {
+    conn: Connection,
+}
+
+impl ResultsStore {
+    pub fn open(path: &str) -> Result<Self, AppError> {
+        if let Some(parent) = Path::new(path).parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    pub fn start_run(&self, run: &RunRecord) -> Result<i64, AppError> {
+        self.conn.execute(
+            "INSERT INTO runs (started_at, data_path, rubric_path, rubric_hash, model_id,
+                               temperature, max_tokens, context_size, gpu_layers,
+                               thread_count, llamafile_kvargs, item_count, seed, timeout)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                chrono::Utc::now().to_rfc3339(),
+                run.data_path,
+                run.rubric_path,
+                run.rubric_hash,
+                run.model_id,
+                run.temperature,
+                to_sql_int(run.max_tokens),
+                to_sql_int(run.context_size),
+                to_sql_int(run.gpu_layers),
+                run.thread_count.map(to_sql_int),
+                run.llamafile_kvargs,
+                to_sql_int(run.item_count),
+                run.seed.map(|seed| seed.to_string()),
+                run.timeout,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn record_judgments(
+        &mut self,
+        run_id: i64,
+        judgments: &[JudgmentRecord],
+    ) -> Result<(), AppError> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert_item = tx.prepare(
+                "INSERT INTO items (run_id, item_index, input, output, fields)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            let mut insert_judgment = tx.prepare(
+                "INSERT INTO judgments (item_id, raw_output, feedback, score, latency_ms,
+                                        attempts, error)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+
+            for judgment in judgments {
+                let item_id = insert_item.insert(params![
+                    run_id,
+                    to_sql_int(judgment.item_index),
+                    judgment.input,
+                    judgment.output,
+                    judgment.fields,
+                ])?;
+                insert_judgment.execute(params![
+                    item_id,
+                    judgment.raw_output,
+                    judgment.feedback,
+                    judgment.score,
+                    i64::try_from(judgment.latency_ms).unwrap_or(i64::MAX),
+                    judgment.attempts,
+                    judgment.error,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn finish_run(&self, run_id: i64, failures: u32) -> Result<(), AppError> {
+        self.conn.execute(
+            "UPDATE runs SET finished_at = ?1, failures = ?2 WHERE id = ?3",
+            params![chrono::Utc::now().to_rfc3339(), failures, run_id],
+        )?;
+        Ok(())
+    }
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+fn to_sql_int(value: usize) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
@@ -1,12 +1,51 @@
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::config::Config;
-    use crate::error::AppError;
-    use crate::processing::{process_io_pairs, update_json_file};
+    use crate::models::{AppError, Config, TaskConfig};
+    use crate::update_json_file;
     use serde_json::json;
+    use std::path::Path;
     use tokio::fs;
 
+    /// Writes an executable shell script standing in for a llamafile and
+    /// returns its path.
+    fn stub_llamafile(dir: &Path, name: &str, body: &str) -> Result<String, AppError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}", body))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+
+    /// Judges the data file `data` in place, as a task with a minimal rubric.
+    async fn judge_data(
+        config: &Config,
+        data: &str,
+        params: &crate::models::RunParams,
+        batch_size: usize,
+        shutdown: &crate::shutdown::Shutdown,
+    ) -> Result<crate::FileSummary, AppError> {
+        use crate::models::{RunConfig, SelectionConfig};
+
+        let task = TaskConfig {
+            name: None,
+            data: vec![data.to_string()],
+            rubric_template: "Input: {{ input }}\nOutput: {{ output }}".to_string(),
+            output: None,
+            run: RunConfig::default(),
+            selection: SelectionConfig::default(),
+        };
+        let mut context = crate::JudgeContext {
+            config,
+            params,
+            selection: &SelectionConfig::default(),
+            results_store: None,
+            shutdown,
+        };
+        let (summary, _) = crate::process_file(data, data, &task, batch_size, &mut context).await?;
+        Ok(summary)
+    }
+
     #[tokio::test]
     async fn test_update_json_file() -> Result<(), AppError> {
         let temp_dir = tempfile::tempdir()?;
@@ -31,24 +70,1585 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_json_round_trip_preserves_unknown_fields() -> Result<(), AppError> {
+        let temp_dir = tempfile::tempdir()?;
+        let file_path = temp_dir.path().join("items.json");
+        let file_path = file_path.to_str().unwrap();
+        std::fs::write(
+            file_path,
+            r#"[
+                {"id": "a-1", "input": "q", "tags": ["x", "y"], "output": "r",
+                 "metadata": {"zeta": 1, "alpha": 2}},
+                {"input": "q2", "output": "r2", "feedback": "old", "score": 2, "id": "a-2"}
+            ]"#,
+        )?;
+
+        let mut items = crate::read_json(file_path)?;
+        items[0].set_feedback(Some("good".to_string()));
+        items[0].set_score(Some(5));
+        items[1].set_score(Some(4));
+        crate::write_json(&items, file_path)?;
+
+        let reread = crate::read_json(file_path)?;
+        let keys: Vec<&str> = reread[0].fields.keys().map(String::as_str).collect();
+        assert_eq!(
+            keys,
+            ["id", "input", "tags", "output", "metadata", "feedback", "score"]
+        );
+        let keys: Vec<&str> = reread[1].fields.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["input", "output", "feedback", "score", "id"]);
+        assert_eq!(reread[0].fields["tags"], json!(["x", "y"]));
+        let nested: Vec<&String> = reread[0].fields["metadata"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect();
+        assert_eq!(nested, ["zeta", "alpha"]);
+        assert_eq!(reread[0].score(), Some(5));
+        assert_eq!(reread[1].feedback(), Some("old"));
+        assert_eq!(reread[1].score(), Some(4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_round_trip_preserves_columns() -> Result<(), AppError> {
+        let temp_dir = tempfile::tempdir()?;
+        let file_path = temp_dir.path().join("items.csv");
+        let file_path = file_path.to_str().unwrap();
+        let original = "id,input,category,output\n1,q1,billing,r1\n2,\"q, 2\",tech,r2\n";
+        std::fs::write(file_path, original)?;
+
+        // Rewriting without changes must reproduce the file exactly
+        let items = crate::read_csv(file_path)?;
+        crate::write_csv(&items, file_path)?;
+        assert_eq!(std::fs::read_to_string(file_path)?, original);
+
+        let mut items = crate::read_csv(file_path)?;
+        items[0].set_feedback(Some("fine".to_string()));
+        items[0].set_score(Some(3));
+        items[1].set_feedback(Some("no score".to_string()));
+        items[1].set_score(None);
+        crate::write_csv(&items, file_path)?;
+
+        assert_eq!(
+            std::fs::read_to_string(file_path)?,
+            "id,input,category,output,feedback,score\n\
+             1,q1,billing,r1,fine,3\n\
+             2,\"q, 2\",tech,r2,no score,\n"
+        );
+
+        let reread = crate::read_csv(file_path)?;
+        assert_eq!(reread[0].score(), Some(3));
+        assert_eq!(reread[1].score(), None);
+        assert_eq!(reread[1].fields["category"], json!("tech"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_rejects_records_without_input() -> Result<(), AppError> {
+        let temp_dir = tempfile::tempdir()?;
+        let file_path = temp_dir.path().join("items.json");
+        std::fs::write(&file_path, r#"[{"output": "r"}]"#)?;
+
+        assert!(crate::read_json(file_path.to_str().unwrap()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_results_store_records_run() -> Result<(), AppError> {
+        use crate::store::{JudgmentRecord, ResultsStore, RunRecord};
+
+        let temp_dir = tempfile::tempdir()?;
+        let db_path = temp_dir.path().join("results").join("fwj.sqlite");
+        let mut store = ResultsStore::open(db_path.to_str().unwrap())?;
+
+        let run_id = store.start_run(&RunRecord {
+            data_path: "data.json".to_string(),
+            rubric_path: "rubric.jinja".to_string(),
+            rubric_hash: crate::store::sha256_hex(b"rubric"),
+            model_id: "flow-judge.llamafile".to_string(),
+            temperature: 0.1,
+            max_tokens: 1000,
+            context_size: 8192,
+            gpu_layers: 34,
+            thread_count: None,
+            llamafile_kvargs: None,
+            item_count: 2,
+            seed: Some(u64::MAX),
+            timeout: Some(30.0),
+        })?;
+        store.record_judgments(
+            run_id,
+            &[
+                JudgmentRecord {
+                    item_index: 0,
+                    input: "q".to_string(),
+                    output: "r".to_string(),
+                    fields: r#"{"input":"q","output":"r"}"#.to_string(),
+                    raw_output: Some("<feedback>ok</feedback><score>4</score>".to_string()),
+                    feedback: Some("ok".to_string()),
+                    score: Some(4),
+                    latency_ms: 1200,
+                    attempts: 1,
+                    error: None,
+                },
+                JudgmentRecord {
+                    item_index: 1,
+                    input: "q2".to_string(),
+                    output: "r2".to_string(),
+                    fields: r#"{"input":"q2","output":"r2"}"#.to_string(),
+                    raw_output: None,
+                    feedback: None,
+                    score: None,
+                    latency_ms: 30,
+                    attempts: 3,
+                    error: Some("Max retries reached".to_string()),
+                },
+            ],
+        )?;
+        store.finish_run(run_id, 1)?;
+        drop(store);
+
+        let conn = rusqlite::Connection::open(&db_path)?;
+        let (judged_score, attempt_count): (Option<i32>, u32) = conn.query_row(
+            "SELECT j.score, j.attempts FROM judgments j JOIN items i ON i.id = j.item_id
+             WHERE i.run_id = ?1 AND i.item_index = 0",
+            [run_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!((judged_score, attempt_count), (Some(4), 1));
+        let failures: u32 =
+            conn.query_row("SELECT failures FROM runs WHERE id = ?1", [run_id], |row| {
+                row.get(0)
+            })?;
+        assert_eq!(failures, 1);
+        // Seeds above i64::MAX are kept exactly rather than clamped
+        let (seed, timeout): (Option<String>, Option<f64>) = conn.query_row(
+            "SELECT seed, timeout FROM runs WHERE id = ?1",
+            [run_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(seed, Some(u64::MAX.to_string()));
+        assert_eq!(timeout, Some(30.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_task_data_accepts_paths_and_globs() -> Result<(), AppError> {
+        let temp_dir = tempfile::tempdir()?;
+        let nested = temp_dir.path().join("data").join("nested");
+        std::fs::create_dir_all(&nested)?;
+        for name in ["b.json", "a.json"] {
+            std::fs::write(temp_dir.path().join("data").join(name), "[]")?;
+        }
+        std::fs::write(nested.join("c.json"), "[]")?;
+        std::fs::write(nested.join("skip.csv"), "")?;
+        let root = temp_dir.path().to_str().unwrap();
+
+        let single: TaskConfig = serde_yml::from_str(&format!(
+            "data: {}/data/a.json\nrubric_template: r.jinja",
+            root
+        ))?;
+        assert_eq!(single.data_files()?, [format!("{}/data/a.json", root)]);
+
+        let many: TaskConfig = serde_yml::from_str(&format!(
+            "data:\n  - {root}/data/**/*.json\n  - {root}/data/a.json\nrubric_template: r.jinja",
+            root = root
+        ))?;
+        assert_eq!(
+            many.data_files()?,
+            [
+                format!("{}/data/a.json", root),
+                format!("{}/data/b.json", root),
+                format!("{}/data/nested/c.json", root),
+            ]
+        );
+
+        let missing: TaskConfig = serde_yml::from_str(&format!(
+            "data: {}/nothing/*.json\nrubric_template: r.jinja",
+            root
+        ))?;
+        assert!(missing.data_files().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_write_keeps_original_file() -> Result<(), AppError> {
+        let temp_dir = tempfile::tempdir()?;
+        let file_path = temp_dir.path().join("items.json");
+        std::fs::write(&file_path, r#"[{"input": "q", "output": "r"}]"#)?;
+
+        let result = crate::write_atomic(file_path.to_str().unwrap(), |file| {
+            file.write_all(b"[{\"input\": ")?;
+            Err(AppError::JsonWriteError("serialization failed".to_string()))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(
+            std::fs::read_to_string(&file_path)?,
+            r#"[{"input": "q", "output": "r"}]"#
+        );
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bak_backup_keeps_first_original() -> Result<(), AppError> {
+        use crate::models::BackupMode;
+
+        let temp_dir = tempfile::tempdir()?;
+        let file_path = temp_dir.path().join("items.csv");
+        let file_path = file_path.to_str().unwrap();
+        std::fs::write(file_path, "input,output\nq,r\n")?;
+
+        let backup = crate::backup_original(file_path, BackupMode::Bak)?.unwrap();
+        std::fs::write(file_path, "input,output,score\nq,r,5\n")?;
+        assert!(crate::backup_original(file_path, BackupMode::Bak)?.is_none());
+        assert_eq!(std::fs::read_to_string(backup)?, "input,output\nq,r\n");
+
+        assert!(crate::backup_original(file_path, BackupMode::None)?.is_none());
+        let stamped = crate::backup_original(file_path, BackupMode::Timestamped)?.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(stamped)?,
+            "input,output,score\nq,r,5\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_task_options_resolve_between_config_and_cli() -> Result<(), AppError> {
+        use crate::cli::Args;
+        use clap::Parser;
+
+        let temp_dir = tempfile::tempdir()?;
+        let config_path = temp_dir.path().join("config.yaml");
+        std::fs::write(
+            &config_path,
+            "temperature: 0.5\n\
+             context_size: 4096\n\
+             tasks:\n\
+             \x20 - data: a.json\n\
+             \x20   rubric_template: r.jinja\n\
+             \x20   output: out/a.json\n\
+             \x20   max_tokens: 200\n\
+             \x20   model: ./other.llamafile\n\
+             \x20 - data: b.json\n\
+             \x20   rubric_template: r.jinja\n",
+        )?;
+        let config = Config::from_file(config_path.to_str().unwrap())?;
+        assert_eq!(config.tasks.len(), 2);
+        assert_eq!(config.tasks[0].output.as_deref(), Some("out/a.json"));
+
+        let args = Args::try_parse_from(["fwj", "--temp", "0.9"]).unwrap();
+        assert!(args.data.is_none());
+        let cli_run = args.run_config();
+
+        let first = cli_run
+            .or(&config.tasks[0].run)
+            .or(&config.run)
+            .resolve();
+        assert!((first.temperature - 0.9).abs() < 1e-9);
+        assert_eq!(first.max_tokens, 200);
+        assert_eq!(first.context_size, 4096);
+        assert_eq!(first.model.as_deref(), Some("./other.llamafile"));
+
+        let second = cli_run
+            .or(&config.tasks[1].run)
+            .or(&config.run)
+            .resolve();
+        assert_eq!(second.max_tokens, crate::models::DEFAULT_MAX_TOKENS);
+        assert_eq!(second.gpu_layers, crate::models::DEFAULT_GPU_LAYERS);
+        assert_eq!(second.model, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_layered_config_tracks_sources() -> Result<(), AppError> {
+        use crate::config::{self, Layer};
+        use serde_json::Map;
+
+        let temp_dir = tempfile::tempdir()?;
+        let user_path = temp_dir.path().join("config.toml");
+        std::fs::write(
+            &user_path,
+            "cache_dir = \"/tmp/fwj-user\"\ntemperature = 0.3\nmax_tokens = 300\n",
+        )?;
+        let project_path = temp_dir.path().join("config.yaml");
+        std::fs::write(&project_path, "max_tokens: 500\nbackup: bak\n")?;
+
+        let user = Config::from_file(user_path.to_str().unwrap())?;
+        assert_eq!(user.cache_dir, "/tmp/fwj-user");
+        assert_eq!(user.run.temperature, Some(0.3));
+
+        let to_map = |path: &std::path::Path| match config::read_config_value(
+            path.to_str().unwrap(),
+        ) {
+            Ok(serde_json::Value::Object(map)) => map,
+            other => panic!("unexpected config value: {:?}", other),
+        };
+        let known_keys = vec!["temperature".to_string(), "results_db".to_string()];
+        let env = config::env_values(
+            vec![
+                ("FWJ_TEMPERATURE".to_string(), "0.7".to_string()),
+                ("FWJ_UNKNOWN".to_string(), "1".to_string()),
+                ("HOME".to_string(), "/root".to_string()),
+            ],
+            &known_keys,
+        );
+        assert_eq!(env.len(), 1);
+        let (var, key, value) = env.into_iter().next().unwrap();
+        let mut env_map = Map::new();
+        env_map.insert(key, value);
+        let mut cli_map = Map::new();
+        cli_map.insert("results_db".to_string(), json!("runs.sqlite"));
+
+        let mut defaults = Map::new();
+        defaults.insert("max_tokens".to_string(), json!(1000));
+        defaults.insert("cache_dir".to_string(), json!("/default"));
+        defaults.insert("results_db".to_string(), serde_json::Value::Null);
+
+        let resolved = config::resolve(vec![
+            (Layer::Default, defaults),
+            (Layer::User(user_path.clone()), to_map(&user_path)),
+            (Layer::Project(project_path.clone()), to_map(&project_path)),
+            (Layer::Env(var), env_map),
+            (Layer::Cli, cli_map),
+        ])?;
+
+        assert_eq!(resolved.config.cache_dir, "/tmp/fwj-user");
+        assert_eq!(resolved.config.run.max_tokens, Some(500));
+        assert_eq!(resolved.config.run.temperature, Some(0.7));
+        assert_eq!(resolved.config.results_db.as_deref(), Some("runs.sqlite"));
+        assert_eq!(resolved.config.backup, crate::models::BackupMode::Bak);
+        assert_eq!(resolved.sources["cache_dir"], Layer::User(user_path));
+        assert_eq!(resolved.sources["max_tokens"], Layer::Project(project_path));
+        assert_eq!(
+            resolved.sources["temperature"],
+            Layer::Env("FWJ_TEMPERATURE".to_string())
+        );
+        assert_eq!(resolved.sources["results_db"], Layer::Cli);
+        Ok(())
+    }
+
+    #[test]
+    fn test_profiles_select_tasks_and_options() -> Result<(), AppError> {
+        let temp_dir = tempfile::tempdir()?;
+        let config_path = temp_dir.path().join("config.yaml");
+        std::fs::write(
+            &config_path,
+            "profiles:\n\
+             \x20 quick:\n\
+             \x20   max_tokens: 128\n\
+             \x20   batch_size: 4\n\
+             \x20   tasks: [smoke-*]\n\
+             \x20 release:\n\
+             \x20   temperature: 0.0\n\
+             tasks:\n\
+             \x20 - name: smoke-subquery\n\
+             \x20   data: a.json\n\
+             \x20   rubric_template: r.jinja\n\
+             \x20   max_tokens: 900\n\
+             \x20 - data: data/full.json\n\
+             \x20   rubric_template: r.jinja\n",
+        )?;
+        let mut config = Config::from_file(config_path.to_str().unwrap())?;
+        assert!(config.active_profile()?.is_none());
+
+        config.profile = Some("quick".to_string());
+        let quick = config.active_profile()?.unwrap().clone();
+        assert_eq!(quick.batch_size, Some(4));
+        assert!(quick.selects(&config.tasks[0])?);
+        assert!(!quick.selects(&config.tasks[1])?);
+        let params = crate::models::RunConfig::default()
+            .or(&quick.run)
+            .or(&config.tasks[0].run)
+            .or(&config.run)
+            .resolve();
+        assert_eq!(params.max_tokens, 128);
+
+        config.profile = Some("release".to_string());
+        let release = config.active_profile()?.unwrap();
+        assert!(release.selects(&config.tasks[1])?);
+        assert_eq!(release.run.temperature, Some(0.0));
+
+        config.profile = Some("nightly".to_string());
+        assert!(config.active_profile().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_selection_filters_shards_and_samples() -> Result<(), AppError> {
+        use crate::models::{IoItem, SelectionConfig};
+        use crate::selection::select;
+
+        let items: Vec<IoItem> = (0..10)
+            .map(|i| {
+                serde_json::from_value(serde_json::json!({
+                    "input": format!("q{}", i),
+                    "output": format!("a{}", i),
+                    "category": if i % 3 == 0 { "billing" } else { "other" },
+                    "score": if i < 4 { serde_json::json!(i) } else { serde_json::Value::Null },
+                }))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let all = select(&items, &SelectionConfig::default())?;
+        assert_eq!(all, (0..10).collect::<Vec<_>>());
+
+        let limited = SelectionConfig {
+            limit: Some(3),
+            ..SelectionConfig::default()
+        };
+        assert_eq!(select(&items, &limited)?, vec![0, 1, 2]);
+
+        let filtered = SelectionConfig {
+            filter: Some("category == billing && score ==".to_string()),
+            ..SelectionConfig::default()
+        };
+        assert_eq!(select(&items, &filtered)?, vec![6, 9]);
+
+        let scored = SelectionConfig {
+            filter: Some("score >= 2".to_string()),
+            ..SelectionConfig::default()
+        };
+        assert_eq!(select(&items, &scored)?, vec![2, 3]);
+
+        let mut shards = Vec::new();
+        for i in 1..=3 {
+            let shard = SelectionConfig {
+                shard: Some(format!("{}/3", i)),
+                ..SelectionConfig::default()
+            };
+            shards.extend(select(&items, &shard)?);
+        }
+        shards.sort_unstable();
+        assert_eq!(shards, all);
+
+        let sample = SelectionConfig {
+            sample: Some(4),
+            sample_seed: Some(7),
+            ..SelectionConfig::default()
+        };
+        let first = select(&items, &sample)?;
+        assert_eq!(first.len(), 4);
+        assert_eq!(first, select(&items, &sample)?);
+
+        let stratified = SelectionConfig {
+            sample: Some(5),
+            sample_seed: Some(7),
+            stratify_by: Some("category".to_string()),
+            ..SelectionConfig::default()
+        };
+        let picked = select(&items, &stratified)?;
+        let billing = picked.iter().filter(|&&i| i % 3 == 0).count();
+        assert_eq!((picked.len(), billing), (5, 2));
+
+        let invalid = SelectionConfig {
+            shard: Some("4/3".to_string()),
+            ..SelectionConfig::default()
+        };
+        assert!(select(&items, &invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_splits_only_outside_quoted_values() -> Result<(), AppError> {
+        use crate::models::IoItem;
+        use crate::selection::Filter;
+
+        let item: IoItem = serde_json::from_value(serde_json::json!({
+            "input": "q",
+            "output": "a",
+            "note": "salt && pepper",
+            "owner": "O'Brien",
+        }))?;
+        assert!(Filter::parse("note == 'salt && pepper' && owner == O'Brien")?.matches(&item));
+        assert!(Filter::parse("note ~ \"t && p\"")?.matches(&item));
+        assert!(!Filter::parse("note == 'salt' && owner == O'Brien")?.matches(&item));
+
+        // Unquoted, the value ends at && and the rest is a clause of its own
+        let unquoted = Filter::parse("note == salt && pepper");
+        assert!(matches!(unquoted, Err(AppError::ConfigError(message)) if message.contains("'pepper'")));
+        let unterminated = Filter::parse("note == 'salt && pepper");
+        assert!(matches!(unterminated, Err(AppError::ConfigError(message)) if message.contains("unterminated")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_combines_shards_and_reports_conflicts() -> Result<(), AppError> {
+        use crate::cli::OnConflict;
+        use crate::merge::merge;
+        use crate::models::IoItem;
+
+        let items = |records: serde_json::Value| -> Result<Vec<IoItem>, AppError> {
+            Ok(serde_json::from_value(records)?)
+        };
+        let shard_1 = items(serde_json::json!([
+            {"id": 1, "input": "q1", "output": "a1", "feedback": "ok", "score": 4},
+            {"id": 2, "input": "q2", "output": "a2", "score": null},
+            {"input": "q3", "output": "a3", "feedback": "fine", "score": 3},
+            {"id": 4, "input": "q4", "output": "a4"},
+        ]))?;
+        let shard_2 = items(serde_json::json!([
+            {"id": 1, "input": "q1", "output": "a1", "feedback": "meh", "score": 2},
+            {"id": 2, "input": "q2", "output": "a2", "feedback": "good", "score": 5},
+            {"input": "q3", "output": "a3", "score": null},
+        ]))?;
+        let sources = vec![
+            ("shard-1.json".to_string(), shard_1),
+            ("shard-2.csv".to_string(), shard_2),
+        ];
+
+        let (merged, report) = merge(&sources, "id", OnConflict::First);
+        assert_eq!(merged.len(), 4);
+        assert_eq!(
+            merged.iter().map(IoItem::score).collect::<Vec<_>>(),
+            vec![Some(4), Some(5), Some(3), None]
+        );
+        assert_eq!(merged[1].fields["feedback"], "good");
+        assert_eq!((report.items, report.judged), (4, 3));
+        assert_eq!(report.files[1].3, 1);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].key, "id=1");
+        assert_eq!(report.gaps.len(), 1);
+        assert_eq!(report.gaps[0].0, 3);
+
+        let (merged, _) = merge(&sources, "id", OnConflict::Last);
+        assert_eq!(merged[0].score(), Some(2));
+        assert_eq!(merged[0].fields["feedback"], "meh");
+        Ok(())
+    }
+
     #[tokio::test]
-    async fn test_process_io_pairs() -> Result<(), AppError> {
-        // This is a more complex test and might require mocking external dependencies
-        // Here's a basic structure:
+    async fn test_judge_single_item_with_stub_llamafile() -> Result<(), AppError> {
+        let temp_dir = tempfile::tempdir()?;
+        let stub = |name: &str, reply: &str| {
+            stub_llamafile(temp_dir.path(), name, &format!("printf '%s' '{}'\n", reply))
+        };
         let config = Config {
-            // ... populate with test values
+            cache_dir: temp_dir.path().to_string_lossy().into_owned(),
+            ..Config::default()
         };
-        let task_config = TaskConfig {
-            // ... populate with test values
+        let rubric = "Input: {{ input }}\nOutput: {{ output }}";
+
+        let mut run = crate::models::RunConfig::defaults();
+        run.model = Some(stub(
+            "good.llamafile",
+            "<feedback>Clear answer.</feedback> <score>4</score>",
+        )?);
+        crate::judge::run(&config, &run.resolve(), rubric, "q", "a").await?;
+        let last_result = std::fs::read_to_string(temp_dir.path().join("last_result.txt"))?;
+        assert!(last_result.contains("<score>4</score>"));
+
+        run.model = Some(stub("bad.llamafile", "no verdict")?);
+        let result = crate::judge::run(&config, &run.resolve(), rubric, "q", "a").await;
+        assert!(matches!(result, Err(AppError::ParseError(_))));
+
+        let (input, output) = crate::judge::read_pair(Some("q"), None, None, None)?;
+        assert_eq!((input.as_str(), output.as_str()), ("q", ""));
+        assert!(crate::judge::read_pair(None, Some("-"), None, Some("-")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_statistics_and_breakdown() -> Result<(), AppError> {
+        use crate::report::{build, ReportOptions};
+
+        let items: Vec<crate::models::IoItem> = serde_json::from_value(serde_json::json!([
+            {"input": "q", "output": "a", "category": "x", "feedback": "f", "score": 1},
+            {"input": "q", "output": "a", "category": "x", "feedback": "f", "score": 3},
+            {"input": "q", "output": "a", "category": "y", "feedback": "f", "score": "5"},
+            {"input": "q", "output": "a", "category": "y", "feedback": "f", "score": 5},
+            {"input": "q", "output": "a", "category": "y", "feedback": "garbled", "score": null},
+            {"input": "q", "output": "a"},
+        ]))?;
+        let options = ReportOptions {
+            by: Some("category".to_string()),
+            resamples: 500,
+            confidence: 0.9,
+            seed: 1,
         };
 
-        // You might need to set up mock files and directories here
+        let report = build("judged.json", &items, &options);
+        let summary = &report.summary;
+        assert_eq!((summary.items, summary.scored), (6, 4));
+        assert_eq!((summary.parse_failures, summary.not_judged), (1, 1));
+        assert!((summary.parse_failure_rate - 0.2).abs() < 1e-9);
+        let mean = summary.mean.unwrap();
+        assert!((mean.value - 3.5).abs() < 1e-9);
+        assert!(mean.low >= 1.0 && mean.low <= mean.value && mean.high <= 5.0);
+        assert!((summary.median.unwrap().value - 4.0).abs() < 1e-9);
+        assert_eq!(summary.histogram[&5], 2);
+
+        assert_eq!(report.groups.keys().collect::<Vec<_>>(), vec!["x", "y", "(none)"]);
+        assert_eq!(report.groups["y"].parse_failures, 1);
+        assert!(report.groups["(none)"].mean.is_none());
+
+        // The same seed gives the same intervals
+        let again = build("judged.json", &items, &options);
+        assert!((again.summary.mean.unwrap().low - mean.low).abs() < 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_aligns_runs_and_tests_shift() -> Result<(), AppError> {
+        use crate::diff::{align, ItemChange};
+        use crate::stats::{ranks, wilcoxon_signed_rank};
+
+        let old: Vec<crate::models::IoItem> = serde_json::from_value(serde_json::json!([
+            {"id": "a", "input": "q1", "output": "x", "feedback": "good", "score": 5},
+            {"input": "q2", "output": "x", "feedback": "ok", "score": 3},
+            {"input": "q3", "output": "x", "feedback": "ok", "score": 3},
+            {"input": "q4", "output": "x"},
+        ]))?;
+        let new: Vec<crate::models::IoItem> = serde_json::from_value(serde_json::json!([
+            {"input": "q3", "output": "rewritten", "feedback": "better", "score": 4},
+            {"id": "a", "input": "q1 edited", "output": "x", "feedback": "wrong", "score": 2},
+            {"input": "q4", "output": "x", "feedback": "fine", "score": 4},
+            {"input": "q5", "output": "x", "feedback": "new", "score": 1},
+        ]))?;
+
+        let alignment = align(&old, &new, "id");
+        assert_eq!((alignment.only_old, alignment.only_new, alignment.unscored), (1, 1, 1));
+        let deltas: Vec<i32> = alignment.changes.iter().map(ItemChange::delta).collect();
+        assert_eq!(deltas, vec![-3, 1]);
+        assert_eq!(alignment.changes[0].new_feedback, "wrong");
+
+        assert_eq!(ranks(&[3.0, 1.0, 3.0, 2.0]), vec![3.5, 1.0, 3.5, 2.0]);
+        let differences = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, -9.0, 10.0, 0.0];
+        let test = wilcoxon_signed_rank(&differences).unwrap();
+        assert_eq!(test.n, 10);
+        assert!((test.statistic - 46.0).abs() < 1e-9);
+        assert!((test.z - 1.8347).abs() < 1e-3);
+        assert!((test.p_value - 0.0666).abs() < 1e-3);
+        assert!(wilcoxon_signed_rank(&[0.0, 0.0]).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_meta_eval_agreement_metrics() -> Result<(), AppError> {
+        use crate::meta_eval::evaluate;
+        use crate::stats::{cohen_kappa, kendall_tau_b, spearman};
 
-        process_io_pairs(&task_config, &config).await?;
+        let mut pairs = vec![(1, 1); 20];
+        pairs.extend(vec![(1, 2); 5]);
+        pairs.extend(vec![(2, 1); 10]);
+        pairs.extend(vec![(2, 2); 15]);
+        assert!((cohen_kappa(&pairs, &[1, 2], false).unwrap() - 0.4).abs() < 1e-9);
+        assert!((cohen_kappa(&pairs, &[1, 2], true).unwrap() - 0.4).abs() < 1e-9);
+        let ordinal = [(1, 1), (1, 2), (2, 3), (3, 3), (3, 1), (2, 2)];
+        assert!((cohen_kappa(&ordinal, &[1, 2, 3], true).unwrap() - 0.25).abs() < 1e-9);
+        assert!(cohen_kappa(&[(3, 3), (3, 3)], &[3], false).is_none());
+        // Scores nobody gave still count towards the distance between 2 and 5
+        let gapped = [(1, 1), (1, 2), (2, 2), (2, 5), (5, 5), (5, 1), (1, 1)];
+        let full = cohen_kappa(&gapped, &[1, 2, 3, 4, 5], true).unwrap();
+        assert!((cohen_kappa(&gapped, &[1, 2, 5], true).unwrap() - full).abs() < 1e-9);
 
-        // Assert on the expected outcomes
-        // This might involve reading and checking the contents of output files
+        let x = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let y = [2.0, 1.0, 4.0, 3.0, 5.0];
+        assert!((spearman(&x, &y).unwrap() - 0.8).abs() < 1e-9);
+        assert!((kendall_tau_b(&x, &y).unwrap() - 0.6).abs() < 1e-9);
 
+        let items: Vec<crate::models::IoItem> = serde_json::from_value(serde_json::json!([
+            {"input": "a", "output": "x", "human_score": 5, "score": 5},
+            {"input": "b", "output": "x", "human_score": "4", "score": 1, "feedback": "bad"},
+            {"input": "c", "output": "x", "human_score": 2, "score": 3},
+            {"input": "d", "output": "x", "human_score": null, "score": 3},
+        ]))?;
+        let result = evaluate("judged.json", &items, "human_score", "score", 1);
+        assert_eq!((result.items, result.paired), (4, 3));
+        assert!((result.exact_match - 1.0 / 3.0).abs() < 1e-9);
+        assert!((result.within_one - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(result.categories, vec![1, 2, 3, 4, 5]);
+        assert_eq!(result.confusion[3][0], 1);
+        assert_eq!(result.disagreements.len(), 1);
+        assert_eq!(result.disagreements[0].item, 2);
+        assert_eq!(result.disagreements[0].feedback, "bad");
+
+        let items: Vec<crate::models::IoItem> = serde_json::from_value(serde_json::json!([
+            {"input": "a", "output": "x", "human_score": 1, "score": 1},
+            {"input": "b", "output": "x", "human_score": 3, "score": 1},
+            {"input": "c", "output": "x", "human_score": 3, "score": 3},
+        ]))?;
+        let result = evaluate("judged.json", &items, "human_score", "score", 1);
+        assert_eq!(result.categories, vec![1, 2, 3]);
+        assert_eq!(result.confusion[1], vec![0, 0, 0]);
+        assert_eq!(result.confusion[2][0], 1);
+        assert!((result.quadratic_kappa.unwrap() - 0.4).abs() < 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_scan_verifies_and_cleans_stale_artifacts() -> Result<(), AppError> {
+        use crate::cache::{
+            clean, scan, sha256_file, strip_legacy_trailer, CleanOptions, LfsPointer, Status,
+        };
+        use crate::store::sha256_hex;
+
+        let temp_dir = tempfile::tempdir()?;
+        let cache_dir = temp_dir.path();
+        let model = b"llamafile bytes".to_vec();
+        let pointer = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+            sha256_hex(&model),
+            model.len()
+        );
+        assert_eq!(
+            LfsPointer::parse(&pointer).map(|pointer| pointer.size),
+            Some(model.len() as u64)
+        );
+
+        // A model with the completion marker of earlier versions still
+        // appended only verifies once the marker is migrated
+        let mut marked = model.clone();
+        marked.extend_from_slice(br#"{"download_complete":true}"#);
+        std::fs::write(cache_dir.join("flow-judge.llamafile"), &marked)?;
+        std::fs::write(cache_dir.join("flow-judge.llamafile.lock"), &pointer)?;
+        std::fs::write(cache_dir.join("broken.llamafile"), b"llamafile bytez")?;
+        std::fs::write(cache_dir.join("broken.llamafile.lock"), &pointer)?;
+        std::fs::write(cache_dir.join("removed.llamafile.lock"), &pointer)?;
+        std::fs::write(cache_dir.join("last_result.txt"), "result")?;
+        assert_eq!(
+            sha256_file(&cache_dir.join("last_result.txt"), Some(3), None)?,
+            sha256_hex(b"res")
+        );
+
+        let status = |name: &str| -> Result<Option<Status>, AppError> {
+            Ok(scan(cache_dir)?
+                .into_iter()
+                .find(|artifact| artifact.name() == name)
+                .map(|artifact| artifact.status))
+        };
+        assert_eq!(status("broken.llamafile")?, Some(Status::Mismatch));
+        assert_eq!(status("flow-judge.llamafile")?, Some(Status::Mismatch));
+        strip_legacy_trailer(&cache_dir.join("flow-judge.llamafile"), model.len() as u64)?;
+        assert_eq!(status("flow-judge.llamafile")?, Some(Status::Verified));
+
+        let config = Config {
+            cache_dir: cache_dir.to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let stale = CleanOptions {
+            stale: true,
+            ..CleanOptions::default()
+        };
+        // The lock and cached hash of a removed model are orphaned and go with it
+        clean(&config, &stale)?;
+        // Lock guard files are left behind on purpose
+        let mut left: Vec<String> = std::fs::read_dir(cache_dir)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>, AppError>>()?
+            .into_iter()
+            .filter(|name| !name.ends_with(crate::lock::GUARD_SUFFIX))
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            vec![
+                "flow-judge.llamafile",
+                "flow-judge.llamafile.lock",
+                "flow-judge.llamafile.sha256",
+                "last_result.txt"
+            ]
+        );
+
+        let old_run_data = CleanOptions {
+            run_data: true,
+            older_than: Some(1),
+            ..CleanOptions::default()
+        };
+        clean(&config, &old_run_data)?;
+        assert!(cache_dir.join("last_result.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_noninteractive_flag_from_env_and_config() -> Result<(), AppError> {
+        use crate::config::{self, Layer};
+        use serde_json::Map;
+
+        let known_keys = vec!["noninteractive".to_string()];
+        for (raw, expected) in [("1", true), ("yes", true), ("0", false), ("off", false)] {
+            let env = config::env_values(
+                vec![("FWJ_NONINTERACTIVE".to_string(), raw.to_string())],
+                &known_keys,
+            );
+            let (var, key, value) = env.into_iter().next().unwrap();
+            let mut env_map = Map::new();
+            env_map.insert(key, value);
+            let resolved = config::resolve(vec![(Layer::Env(var), env_map)])?;
+            assert_eq!(resolved.config.noninteractive, expected, "{}", raw);
+        }
+
+        let temp_dir = tempfile::tempdir()?;
+        let config_path = temp_dir.path().join("config.yaml");
+        std::fs::write(&config_path, "noninteractive: true\n")?;
+        assert!(Config::from_file(config_path.to_str().unwrap())?.noninteractive);
+        assert!(!Config::default().noninteractive);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_offline_uses_only_verified_cache() -> Result<(), AppError> {
+        use crate::cache::{lock_path, LLAMAFILE_NAME};
+        use crate::download::{download_flow_judge_llamafile, missing_llamafile_artifacts};
+        use crate::store::sha256_hex;
+
+        let temp_dir = tempfile::tempdir()?;
+        let config = Config {
+            cache_dir: temp_dir.path().to_string_lossy().into_owned(),
+            // Would fail to resolve if anything tried the network
+            llamafile_url: "http://fwj.invalid/model.llamafile".to_string(),
+            offline: true,
+            noninteractive: true,
+            ..Config::default()
+        };
+
+        assert_eq!(missing_llamafile_artifacts(&config).len(), 2);
+        let error = download_flow_judge_llamafile(&config).await.unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("2 cached artifact(s) are missing"), "{}", message);
+        assert!(message.contains(LLAMAFILE_NAME), "{}", message);
+
+        let model_path = temp_dir.path().join(LLAMAFILE_NAME);
+        let model = b"cached llamafile".to_vec();
+        std::fs::write(&model_path, &model)?;
+        std::fs::write(
+            lock_path(&model_path),
+            format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+                sha256_hex(&model),
+                model.len()
+            ),
+        )?;
+        assert!(missing_llamafile_artifacts(&config).is_empty());
+        download_flow_judge_llamafile(&config).await?;
+
+        std::fs::write(&model_path, b"tampered llamafile")?;
+        let error = download_flow_judge_llamafile(&config).await.unwrap_err();
+        assert!(error.to_string().contains("does not match"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_hash_is_verified_and_cached() -> Result<(), AppError> {
+        use crate::cache::{checksum_path, lock_path, verify_model, Status};
+        use crate::store::sha256_hex;
+
+        let temp_dir = tempfile::tempdir()?;
+        let model_path = temp_dir.path().join("judge.llamafile");
+        let model = b"model weights".to_vec();
+        std::fs::write(&model_path, &model)?;
+        std::fs::write(
+            lock_path(&model_path),
+            format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+                sha256_hex(&model),
+                model.len()
+            ),
+        )?;
+
+        let size = std::fs::metadata(&model_path)?.len();
+        let (sha256, status) = verify_model(&model_path, size)?;
+        assert_eq!(status, Status::Verified);
+        assert_eq!(sha256.as_deref(), Some(sha256_hex(&model).as_str()));
+
+        // An unchanged model is not hashed again: a forged cache entry wins
+        let checksum = std::fs::read_to_string(checksum_path(&model_path))?;
+        let forged = checksum.replace(&sha256_hex(&model), &"0".repeat(64));
+        std::fs::write(checksum_path(&model_path), forged)?;
+        assert_eq!(verify_model(&model_path, size)?.1, Status::Mismatch);
+
+        // Changing the model invalidates the cached hash
+        std::fs::write(&model_path, b"model weightz")?;
+        assert_eq!(verify_model(&model_path, size)?.1, Status::Mismatch);
+        std::fs::write(&model_path, &model)?;
+        assert_eq!(verify_model(&model_path, size)?.1, Status::Verified);
+
+        // Bytes appended after the locked size are a mismatch
+        let marked = [model.as_slice(), br#"{"download_complete":true}"#].concat();
+        std::fs::write(&model_path, marked)?;
+        let size = std::fs::metadata(&model_path)?.len();
+        assert_eq!(verify_model(&model_path, size)?, (None, Status::Mismatch));
+        Ok(())
+    }
+
+    /// Bytes served per connection by [`serve_dropping_connections`].
+    const RESUME_CHUNK: usize = 10_000;
+
+    /// Serves `body` honouring `Range`, but hangs up after one chunk on each
+    /// of the first three requests and answers the fourth with just one chunk
+    /// of a file of unknown size. The `Range` start of every request is
+    /// recorded in `ranges`.
+    async fn serve_dropping_connections(
+        body: std::sync::Arc<Vec<u8>>,
+        ranges: std::sync::Arc<std::sync::Mutex<Vec<Option<usize>>>>,
+    ) -> Result<(std::net::SocketAddr, tokio::task::JoinHandle<()>), AppError> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            for request in 0.. {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    break;
+                };
+                let mut head = Vec::new();
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    if socket.read(&mut byte).await.unwrap_or(0) == 0 {
+                        break;
+                    }
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8_lossy(&head).to_lowercase();
+                let start: Option<usize> = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim().trim_end_matches('-').parse().ok());
+                ranges.lock().unwrap().push(start);
+
+                let from = start.unwrap_or(0);
+                let end = if request < 4 {
+                    (from + RESUME_CHUNK).min(body.len())
+                } else {
+                    body.len()
+                };
+                let (last, total) = if request == 3 {
+                    (end, "*".to_string())
+                } else {
+                    (body.len(), body.len().to_string())
+                };
+                let status = if start.is_some() {
+                    format!(
+                        "206 Partial Content\r\nContent-Range: bytes {}-{}/{}",
+                        from,
+                        last - 1,
+                        total
+                    )
+                } else {
+                    "200 OK".to_string()
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    last - from
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.write_all(&body[from..end]).await;
+                let _ = socket.flush().await;
+            }
+        });
+        Ok((address, server))
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_after_dropped_connections() -> Result<(), AppError> {
+        use crate::cache::LfsPointer;
+        use crate::download::{download_resumable, part_path};
+        use crate::store::sha256_hex;
+        use std::sync::{Arc, Mutex};
+
+        let body: Arc<Vec<u8>> = Arc::new((0..45_000u32).map(|i| (i * 31 % 251) as u8).collect());
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let (address, server) = serve_dropping_connections(body.clone(), ranges.clone()).await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("model.llamafile");
+        let pointer = LfsPointer {
+            sha256: sha256_hex(&body),
+            size: body.len() as u64,
+        };
+        let url = format!("http://{}/model.llamafile", address);
+        let client = reqwest::Client::new();
+        let progress = indicatif::ProgressBar::hidden();
+        let delay = std::time::Duration::from_millis(5);
+
+        let sha256 =
+            download_resumable(&client, &url, &path, &pointer, &progress, 5, delay).await?;
+        assert_eq!(sha256, pointer.sha256);
+        assert_eq!(std::fs::read(&path)?, *body);
+        assert!(!part_path(&path).exists());
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            vec![
+                None,
+                Some(RESUME_CHUNK),
+                Some(2 * RESUME_CHUNK),
+                Some(3 * RESUME_CHUNK),
+                Some(4 * RESUME_CHUNK)
+            ]
+        );
+        assert_eq!(progress.length(), Some(body.len() as u64));
+
+        // A download that does not match the lock file is never put in place
+        let other = temp_dir.path().join("other.llamafile");
+        let wrong = LfsPointer {
+            sha256: "0".repeat(64),
+            size: body.len() as u64,
+        };
+        let error = download_resumable(&client, &url, &other, &wrong, &progress, 5, delay)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("does not match"), "{}", error);
+        assert!(!other.exists() && !part_path(&other).exists());
+
+        // Giving up after the retries keeps the part file to resume from later
+        ranges.lock().unwrap().clear();
+        server.abort();
+        let error = download_resumable(&client, &url, &other, &pointer, &progress, 1, delay)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AppError::HttpError(_)), "{}", error);
+        assert!(part_path(&other).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_legacy_completion_marker_moves_to_manifest() -> Result<(), AppError> {
+        use crate::cache::{
+            lock_path, manifest_path, strip_legacy_trailer, verify_model, Manifest, Status,
+            LLAMAFILE_NAME,
+        };
+        use crate::download::download_flow_judge_llamafile;
+        use crate::store::sha256_hex;
+
+        let temp_dir = tempfile::tempdir()?;
+        let model_path = temp_dir.path().join(LLAMAFILE_NAME);
+        let model = b"MZqFpD llamafile".to_vec();
+        std::fs::write(
+            lock_path(&model_path),
+            format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+                sha256_hex(&model),
+                model.len()
+            ),
+        )?;
+
+        // Trailing bytes that are not the marker are left alone, for
+        // verification to reject
+        std::fs::write(&model_path, [model.as_slice(), b"garbage"].concat())?;
+        assert_eq!(strip_legacy_trailer(&model_path, model.len() as u64)?, None);
+        let size = std::fs::metadata(&model_path)?.len();
+        assert_eq!(verify_model(&model_path, size)?.1, Status::Mismatch);
+
+        let trailer = br#"{"download_complete":true,"timestamp":"2024-09-25T10:00:00+00:00"}"#;
+        std::fs::write(&model_path, [model.as_slice(), trailer].concat())?;
+        let config = Config {
+            cache_dir: temp_dir.path().to_string_lossy().into_owned(),
+            offline: true,
+            noninteractive: true,
+            ..Config::default()
+        };
+        download_flow_judge_llamafile(&config).await?;
+
+        assert_eq!(std::fs::read(&model_path)?, model);
+        let manifest = Manifest::read(&manifest_path(&model_path)).expect("manifest");
+        assert_eq!(manifest.sha256, sha256_hex(&model));
+        assert_eq!(manifest.size, model.len() as u64);
+        assert_eq!(manifest.downloaded_at, "2024-09-25T10:00:00+00:00");
+        assert_eq!(manifest.url, config.llamafile_url);
+
+        // Migrating again is a no-op
+        download_flow_judge_llamafile(&config).await?;
+        assert_eq!(std::fs::read(&model_path)?, model);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_locks_exclude_other_holders() -> Result<(), AppError> {
+        use crate::cache::{clean, scan, CleanOptions, Status};
+        use crate::lock::FileLock;
+
+        let temp_dir = tempfile::tempdir()?;
+        let model = temp_dir.path().join("judge.llamafile");
+        std::fs::write(&model, b"model")?;
+
+        // Locks are per open file, so they conflict within one process too
+        let shared = FileLock::shared(&model)?;
+        assert!(FileLock::try_shared(&model)?.is_some());
+        assert!(FileLock::try_exclusive(&model)?.is_none());
+        drop(shared);
+
+        let exclusive = FileLock::exclusive(&model)?;
+        assert!(FileLock::try_shared(&model)?.is_none());
+        let artifacts = scan(temp_dir.path())?;
+        assert_eq!(artifacts.len(), 1, "guard files are not listed");
+        assert_eq!(artifacts[0].status, Status::Busy);
+
+        let config = Config {
+            cache_dir: temp_dir.path().to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let all = CleanOptions {
+            all: true,
+            ..CleanOptions::default()
+        };
+        clean(&config, &all)?;
+        assert!(model.exists(), "a model in use is not removed");
+
+        // A waiting writer gets the lock once it is released
+        let waiter = {
+            let model = model.clone();
+            std::thread::spawn(move || FileLock::exclusive(&model).map(drop))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+        drop(exclusive);
+        waiter.join().unwrap()?;
+
+        clean(&config, &all)?;
+        assert!(!model.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sources_accept_mirrors_and_local_paths() -> Result<(), AppError> {
+        use crate::cache::{manifest_path, Manifest, LLAMAFILE_NAME};
+        use crate::download::{download_file, download_flow_judge_llamafile};
+        use crate::source::{http_client, llamafile_source, lock_source, resolve, Source};
+        use crate::store::sha256_hex;
+
+        let config = Config::default();
+        assert_eq!(
+            lock_source(&config),
+            Source::Http(
+                "https://huggingface.co/sariola/flow-judge-llamafile/raw/main/flow-judge.llamafile"
+                    .to_string()
+            )
+        );
+        let mirrored = Config {
+            hf_endpoint: Some("https://hf.internal.example/".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(
+            llamafile_source(&mirrored).to_string(),
+            "https://hf.internal.example/sariola/flow-judge-llamafile/resolve/main/flow-judge.llamafile"
+        );
+        assert_eq!(
+            resolve(&mirrored, "https://huggingface.co.evil/x"),
+            Source::Http("https://huggingface.co.evil/x".to_string())
+        );
+        assert_eq!(
+            resolve(&config, "file:///srv/models/judge.llamafile"),
+            Source::File("/srv/models/judge.llamafile".into())
+        );
+        assert_eq!(resolve(&config, "models/judge.llamafile"), Source::File("models/judge.llamafile".into()));
+
+        let broken = Config {
+            ca_bundle: Some("/nonexistent/ca.pem".to_string()),
+            ..Config::default()
+        };
+        assert!(matches!(http_client(&broken), Err(AppError::ConfigError(_))));
+
+        // A mirror on the local file system, lock file next to the model
+        let mirror = tempfile::tempdir()?;
+        let model = b"mirrored llamafile".to_vec();
+        let model_source = mirror.path().join("judge.llamafile");
+        std::fs::write(&model_source, &model)?;
+        std::fs::write(
+            mirror.path().join("judge.llamafile.lock"),
+            format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+                sha256_hex(&model),
+                model.len()
+            ),
+        )?;
+        std::fs::write(mirror.path().join("data.json"), "[]")?;
+
+        let cache = tempfile::tempdir()?;
+        let config = Config {
+            cache_dir: cache.path().to_string_lossy().into_owned(),
+            llamafile_url: format!("file://{}", model_source.display()),
+            noninteractive: true,
+            ..Config::default()
+        };
+        download_flow_judge_llamafile(&config).await?;
+        let cached = cache.path().join(LLAMAFILE_NAME);
+        assert_eq!(std::fs::read(&cached)?, model);
+        let manifest = Manifest::read(&manifest_path(&cached)).expect("manifest");
+        assert_eq!(manifest.url, model_source.display().to_string());
+
+        let data = cache.path().join("data").join("subquery-data.json");
+        let data_source = Source::parse(&mirror.path().join("data.json").to_string_lossy());
+        download_file(&http_client(&config)?, &data_source, &data.to_string_lossy()).await?;
+        assert_eq!(std::fs::read_to_string(&data)?, "[]");
+        Ok(())
+    }
+
+    /// Puts `model` into `cache` together with a lock file it verifies against.
+    fn cache_verified_model(cache: &Path, model: &[u8]) -> Result<(), AppError> {
+        use crate::cache::{lock_path, LLAMAFILE_NAME};
+        use crate::store::sha256_hex;
+
+        std::fs::create_dir_all(cache)?;
+        std::fs::write(cache.join(LLAMAFILE_NAME), model)?;
+        std::fs::write(
+            lock_path(&cache.join(LLAMAFILE_NAME)),
+            format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+                sha256_hex(model),
+                model.len()
+            ),
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_round_trip_verifies_contents() -> Result<(), AppError> {
+        use crate::bundle::{export, import};
+        use crate::cache::{manifest_path, Manifest, LLAMAFILE_NAME};
+
+        let source = tempfile::tempdir()?;
+        let cache = source.path().join("cache");
+        let model = b"bundled llamafile".to_vec();
+        cache_verified_model(&cache, &model)?;
+        let rubrics = source.path().join("rubrics");
+        std::fs::create_dir_all(&rubrics)?;
+        std::fs::write(rubrics.join("judge.jinja"), "Rubric: {{ input }}")?;
+        let config_file = source.path().join("config.yaml");
+        std::fs::write(&config_file, "tasks: []\n")?;
+        let config = Config {
+            cache_dir: cache.to_string_lossy().into_owned(),
+            rubrics_dir: rubrics.to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+
+        for name in ["judge.tar.gz", "judge.zip"] {
+            let archive = source.path().join(name);
+            export(&config, &config_file.to_string_lossy(), &archive)?;
+
+            let target = tempfile::tempdir()?;
+            let imported = Config {
+                cache_dir: target.path().join("cache").to_string_lossy().into_owned(),
+                ..Config::default()
+            };
+            let dest = target.path().join("project");
+            import(&imported, &archive, &dest, false)?;
+            let installed = target.path().join("cache").join(LLAMAFILE_NAME);
+            assert_eq!(std::fs::read(&installed)?, model);
+            assert!(Manifest::read(&manifest_path(&installed)).is_some());
+            assert_eq!(
+                std::fs::read_to_string(dest.join("rubrics").join("judge.jinja"))?,
+                "Rubric: {{ input }}"
+            );
+            assert_eq!(std::fs::read_to_string(dest.join("config.yaml"))?, "tasks: []\n");
+
+            // Importing again is a no-op; local edits are only replaced with --force
+            import(&imported, &archive, &dest, false)?;
+            std::fs::write(dest.join("config.yaml"), "tasks: [local]\n")?;
+            let conflict = import(&imported, &archive, &dest, false);
+            assert!(matches!(conflict, Err(AppError::BundleError(_))));
+            import(&imported, &archive, &dest, true)?;
+            assert_eq!(std::fs::read_to_string(dest.join("config.yaml"))?, "tasks: []\n");
+        }
+
+        // A bundle whose contents no longer match its manifest is rejected
+        let archive = source.path().join("judge.tar");
+        export(&config, &config_file.to_string_lossy(), &archive)?;
+        let mut bytes = std::fs::read(&archive)?;
+        let offset = bytes
+            .windows(6)
+            .position(|window| window == b"Rubric")
+            .expect("rubric in archive");
+        bytes[offset] = b'r';
+        std::fs::write(&archive, bytes)?;
+        let target = tempfile::tempdir()?;
+        let imported = Config {
+            cache_dir: target.path().join("cache").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let tampered = import(&imported, &archive, target.path(), false);
+        assert!(matches!(tampered, Err(AppError::BundleError(message)) if message.contains("SHA-256")));
+        assert!(!target.path().join("cache").join(LLAMAFILE_NAME).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_points_config_at_relocated_rubrics() -> Result<(), AppError> {
+        use crate::bundle::{export, import};
+
+        let source = tempfile::tempdir()?;
+        let cache = source.path().join("cache");
+        cache_verified_model(&cache, b"bundled llamafile")?;
+        let shared = source.path().join("shared");
+        std::fs::create_dir_all(&shared)?;
+        std::fs::write(shared.join("absolute.jinja"), "Absolute: {{ input }}")?;
+        let config_file = source.path().join("config.yaml");
+        std::fs::write(
+            &config_file,
+            format!(
+                "tasks:\n  - data: data.json\n    rubric_template: {}\n",
+                shared.join("absolute.jinja").display()
+            ),
+        )?;
+        let config = Config {
+            cache_dir: cache.to_string_lossy().into_owned(),
+            rubrics_dir: source.path().join("rubrics").to_string_lossy().into_owned(),
+            ..Config::from_file(&config_file.to_string_lossy())?
+        };
+        let archive = source.path().join("judge.tar");
+        export(&config, &config_file.to_string_lossy(), &archive)?;
+
+        // The absolute template is bundled under rubrics/ and the config follows it
+        let target = tempfile::tempdir()?;
+        let imported = Config {
+            cache_dir: target.path().join("cache").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let dest = target.path().join("project");
+        import(&imported, &archive, &dest, false)?;
+        assert_eq!(
+            std::fs::read_to_string(dest.join("rubrics").join("absolute.jinja"))?,
+            "Absolute: {{ input }}"
+        );
+        let bundled = Config::from_file(&dest.join("config.yaml").to_string_lossy())?;
+        assert_eq!(bundled.tasks[0].rubric_template, "rubrics/absolute.jinja");
+        assert_eq!(bundled.tasks[0].data, ["data.json"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_result_cache_reuses_unchanged_judgments() -> Result<(), AppError> {
+        use crate::models::RunConfig;
+        use crate::shutdown::Shutdown;
+
+        let temp_dir = tempfile::tempdir()?;
+        let calls = temp_dir.path().join("calls");
+        let stub = stub_llamafile(
+            temp_dir.path(),
+            "judge.llamafile",
+            &format!(
+                "echo >> '{}'\nprintf '%s' '<feedback>Fine.</feedback> <score>3</score>'\n",
+                calls.display()
+            ),
+        )?;
+        let data = temp_dir.path().join("data.json");
+        std::fs::write(
+            &data,
+            r#"[{"input": "a", "output": "1"}, {"input": "b", "output": "2"}]"#,
+        )?;
+        let data = data.to_string_lossy().into_owned();
+        let mut run = RunConfig::defaults();
+        run.model = Some(stub);
+        let mut config = Config {
+            cache_dir: temp_dir.path().join("cache").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let calls_made = || std::fs::read_to_string(&calls).map_or(0, |calls| calls.lines().count());
+
+        let judge = |config: Config, params: crate::models::RunParams| {
+            let data = data.clone();
+            async move {
+                let summary = judge_data(&config, &data, &params, 2, &Shutdown::new()).await?;
+                Ok::<_, AppError>(summary.cache_hits)
+            }
+        };
+
+        assert_eq!(judge(config.clone(), run.resolve()).await?, Some(0));
+        assert_eq!(calls_made(), 2);
+        assert_eq!(judge(config.clone(), run.resolve()).await?, Some(2));
+        assert_eq!(calls_made(), 2);
+        let items = crate::read_items(&data)?;
+        assert!(items.iter().all(|item| item.score() == Some(3)));
+
+        // Other sampling parameters are judged anew
+        run.seed = Some(7);
+        assert_eq!(judge(config.clone(), run.resolve()).await?, Some(0));
+        assert_eq!(calls_made(), 4);
+
+        config.refresh = true;
+        assert_eq!(judge(config.clone(), run.resolve()).await?, Some(0));
+        assert_eq!(calls_made(), 6);
+        config.refresh = false;
+        config.no_cache = true;
+        assert_eq!(judge(config.clone(), run.resolve()).await?, None);
+        assert_eq!(calls_made(), 8);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_and_shutdown_keep_completed_judgments() -> Result<(), AppError> {
+        use crate::models::RunConfig;
+        use crate::shutdown::Shutdown;
+        use std::time::{Duration, Instant};
+
+        let temp_dir = tempfile::tempdir()?;
+        let stub = |name: &str, seconds: f64| {
+            stub_llamafile(
+                temp_dir.path(),
+                name,
+                &format!(
+                    "sleep {}\nprintf '%s' '<feedback>Fine.</feedback> <score>4</score>'\n",
+                    seconds
+                ),
+            )
+        };
+        let data = temp_dir.path().join("data.json");
+        let data = data.to_string_lossy().into_owned();
+        let reset = || {
+            std::fs::write(
+                &data,
+                r#"[{"input": "a", "output": "1"}, {"input": "b", "output": "2"}, {"input": "c", "output": "3"}]"#,
+            )
+        };
+        let config = Config {
+            cache_dir: temp_dir.path().join("cache").to_string_lossy().into_owned(),
+            no_cache: true,
+            ..Config::default()
+        };
+        let judge = |params: crate::models::RunParams, batch_size: usize, shutdown: Shutdown| {
+            let (data, config) = (data.clone(), config.clone());
+            async move { judge_data(&config, &data, &params, batch_size, &shutdown).await }
+        };
+
+        // A hung judge is killed and its item marked as timed out, keeping
+        // an earlier judgment
+        std::fs::write(
+            &data,
+            r#"[{"input": "a", "output": "1", "feedback": "Earlier.", "score": 5}, {"input": "b", "output": "2"}, {"input": "c", "output": "3"}]"#,
+        )?;
+        let mut run = RunConfig::defaults();
+        run.model = Some(stub("hung.llamafile", 30.0)?);
+        run.timeout = Some(0.3);
+        let start = Instant::now();
+        let summary = judge(run.resolve(), 3, Shutdown::new()).await?;
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!((summary.items, summary.failures, summary.timeouts), (3, 0, 3));
+        let items = crate::read_items(&data)?;
+        assert!(items.iter().all(crate::models::IoItem::timed_out));
+        assert_eq!((items[0].score(), items[0].feedback()), (Some(5), Some("Earlier.")));
+        assert_eq!((items[1].score(), items[1].feedback()), (None, None));
+        let options = crate::report::ReportOptions {
+            by: None,
+            resamples: 10,
+            confidence: 0.9,
+            seed: 1,
+        };
+        let summary = crate::report::build(&data, &items, &options).summary;
+        assert_eq!((summary.parse_failures, summary.timed_out), (0, 3));
+
+        // Judging the item again removes the mark
+        run.model = Some(stub("fast.llamafile", 0.0)?);
+        run.timeout = None;
+        judge(run.resolve(), 3, Shutdown::new()).await?;
+        let items = crate::read_items(&data)?;
+        assert!(items.iter().all(|item| !item.timed_out() && item.score() == Some(4)));
+
+        // The first request lets the item in flight finish but starts no others
+        reset()?;
+        run.model = Some(stub("slow.llamafile", 0.5)?);
+        run.timeout = None;
+        let shutdown = Shutdown::new();
+        let stopper = shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            stopper.request();
+        });
+        let summary = judge(run.resolve(), 1, shutdown).await?;
+        assert_eq!((summary.items, summary.failures), (1, 0));
+        let scores: Vec<_> = crate::read_items(&data)?.iter().map(crate::models::IoItem::score).collect();
+        assert_eq!(scores, vec![Some(4), None, None]);
+
+        // The second cancels the items in flight, leaving them unchanged
+        reset()?;
+        run.model = Some(stub("hung.llamafile", 30.0)?);
+        let shutdown = Shutdown::new();
+        let stopper = shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            stopper.request();
+            stopper.request();
+        });
+        let start = Instant::now();
+        let summary = judge(run.resolve(), 2, shutdown).await?;
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!((summary.items, summary.failures), (0, 0));
+        assert!(crate::read_items(&data)?.iter().all(|item| item.score().is_none()));
+
+        // Judgments without a score are counted as parse failures
+        reset()?;
+        run.model = Some(stub_llamafile(
+            temp_dir.path(),
+            "vague.llamafile",
+            "printf '%s' 'no verdict'\n",
+        )?);
+        let summary = judge(run.resolve(), 3, Shutdown::new()).await?;
+        assert_eq!((summary.items, summary.failures), (3, 3));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_thread_budget_is_split_and_benchmarked() -> Result<(), AppError> {
+        use crate::bench::{configurations, recommend};
+        use crate::{available_threads, parse_generated_tokens, threads_per_worker};
+        use clap::Parser;
+
+        let available = available_threads();
+        assert_eq!(threads_per_worker(Some(3), 4), 3);
+        assert_eq!(threads_per_worker(None, 1), available);
+        assert_eq!(threads_per_worker(None, 2), (available / 2).max(1));
+        assert_eq!(threads_per_worker(None, available * 2), 1);
+        assert!(crate::cli::Args::try_parse_from(["fwj", "--thread-count", "0"]).is_err());
+
+        let stderr = "llama_print_timings: prompt eval time =  812.10 ms /   211 tokens\n\
+                      llama_print_timings:        eval time = 1640.52 ms /    87 runs\n";
+        assert_eq!(parse_generated_tokens(stderr), Some(87));
+        assert_eq!(parse_generated_tokens("no timings"), None);
+
+        assert_eq!(
+            configurations(8, &[], &[]),
+            vec![(1, 8), (1, 4), (2, 4), (2, 2), (4, 2), (4, 1), (8, 1)]
+        );
+        assert_eq!(configurations(1, &[], &[]), vec![(1, 1)]);
+        assert_eq!(configurations(8, &[2], &[3, 3, 0]), vec![(2, 3), (2, 1)]);
+
+        // A stub llamafile that records its thread count and reports 10 tokens
+        let temp_dir = tempfile::tempdir()?;
+        let calls = temp_dir.path().join("calls");
+        let stub = stub_llamafile(
+            temp_dir.path(),
+            "bench.llamafile",
+            &format!(
+                "while [ $# -gt 0 ]; do [ \"$1\" = -t ] && echo \"$2\" >> '{}'; shift; done\n\
+                 echo 'llama_print_timings:        eval time = 10.00 ms /    10 runs' >&2\n\
+                 printf '%s' '<feedback>Fine.</feedback> <score>5</score>'\n",
+                calls.display()
+            ),
+        )?;
+        let data = temp_dir.path().join("data.json");
+        std::fs::write(&data, r#"[{"input": "a", "output": "1"}]"#)?;
+
+        let mut run = crate::models::RunConfig::defaults();
+        run.model = Some(stub);
+        let config = Config {
+            cache_dir: temp_dir.path().to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let measurements = crate::bench::run(
+            &config,
+            &run.resolve(),
+            "{{ input }} {{ output }}",
+            &data.to_string_lossy(),
+            3,
+            &[1, 2],
+            &[2],
+        )
+        .await?;
+        assert_eq!(measurements.len(), 2);
+        assert!(measurements
+            .iter()
+            .all(|measurement| measurement.tokens == 30 && !measurement.estimated));
+        assert!(recommend(&measurements).is_some());
+        // One warm-up run with the default split, then 3 items per configuration
+        let threads: Vec<String> = std::fs::read_to_string(&calls)?
+            .lines()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(threads.len(), 7);
+        assert_eq!(threads[0], available.to_string());
+        assert!(threads[1..].iter().all(|count| count == "2"));
         Ok(())
     }
 }